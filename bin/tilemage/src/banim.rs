@@ -0,0 +1,225 @@
+use std::{collections::HashMap, fmt::Write as _, fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use image::ImageFormat;
+
+use crate::{gbagfx, label_from_name, load_palette, obj::parse_origin};
+
+use gbagfx::banim::{self, BanimOptions};
+use gbagfx::frames;
+
+// Size of one battle animation table entry.
+const ENTRY_SIZE: usize = 0x20;
+
+// Where the cartridge is mapped, which `POIN` adds to offsets.
+const ROM_BASE: u32 = 0x0800_0000;
+
+// Animation names are stored in the table, NUL-padded.
+const NAME_LENGTH: usize = 12;
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct BanimArgs {
+    /// Animation script. Frame images are looked up relative to it.
+    script: PathBuf,
+    /// Directory to write the animation data and installer to.
+    #[arg(short, long, default_value = ".")]
+    out_dir: PathBuf,
+    /// Name stored in the animation table (at most 12 characters), also
+    /// used for output files. Defaults to the script's file name.
+    #[arg(long)]
+    name: Option<String>,
+    /// Index of the animation table entry to write.
+    #[arg(long)]
+    index: usize,
+    /// Where the unit stands in each frame, in pixels, as X,Y.
+    #[arg(long, value_parser = parse_origin, default_value = "148,88")]
+    origin: (i32, i32),
+    /// Use the specified palette instead of deriving one from all frames.
+    #[arg(long)]
+    palette_in: Option<String>,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl BanimArgs {
+    pub fn run(self) -> Result<()> {
+        let script = fs::read_to_string(&self.script)?;
+        let modes = banim::parse_script(&script)?;
+        let dir = self.script.parent().map(PathBuf::from).unwrap_or_default();
+
+        let mut inputs = Vec::new();
+        for name in banim::frame_images(&modes) {
+            let path = dir.join(name);
+            let format = ImageFormat::from_path(&path).ok();
+            let input = fs::read(&path)
+                .with_context(|| format!("couldn't read frame {}", name))?;
+            inputs.push((name, input, format));
+        }
+        if inputs.is_empty() {
+            bail!("script has no frames");
+        }
+
+        // Every frame has to share a palette, so unless one is given, it's
+        // inferred from all of them together. Index 0 is transparent, as in
+        // any sprite.
+        let opts = gbagfx::ConvertOptions {
+            transparency: Some(Default::default()),
+            ..Default::default()
+        };
+        let palette = match self.palette_in {
+            Some(s) => load_palette(s)?,
+            None => {
+                let images = inputs
+                    .iter()
+                    .map(|(name, input, format)| {
+                        let image = gbagfx::decode_image(&input[..], *format)
+                            .with_context(|| {
+                            format!("couldn't decode frame {}", name)
+                        })?;
+                        Ok(image.to_rgba8())
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let palette = frames::shared_palette(&images, &opts)?;
+                if palette.len() > 16 {
+                    bail!(
+                        "the frames use {} colors between them, but a sprite \
+                         palette only has 16",
+                        palette.len()
+                    );
+                }
+                palette
+            }
+        };
+
+        let mut frames = HashMap::new();
+        for (name, input, format) in inputs {
+            let image = gbagfx::convert_image_with_options(
+                &input[..],
+                format,
+                gbagfx::ConvertOptions {
+                    palette: Some(palette.clone()),
+                    ..opts.clone()
+                },
+            )
+            .with_context(|| format!("couldn't convert frame {}", name))?;
+            image.validate()?;
+            frames.insert(name.to_string(), image);
+        }
+
+        let name = match self.name {
+            Some(name) => name,
+            None => self
+                .script
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow!("can't name output; use --name"))?,
+        };
+        if name.len() > NAME_LENGTH || !name.is_ascii() {
+            bail!("animation names must be at most 12 ASCII characters");
+        }
+
+        let anim = banim::compile(
+            &modes,
+            &frames,
+            &palette,
+            &BanimOptions {
+                origin: self.origin,
+            },
+        )?;
+
+        let data_file = format!("{}.bin", name);
+        fs::write(self.out_dir.join(&data_file), &anim.data)?;
+
+        let label = label_from_name(&name);
+        let mut name_bytes = name.clone().into_bytes();
+        name_bytes.resize(NAME_LENGTH, 0);
+        let pointer = |offset: usize| format!("{}+{:#X}", label, offset);
+
+        let mut installer = String::new();
+        writeln!(installer, "// Battle animation installer for {}.", name)?;
+        writeln!(installer)?;
+        writeln!(installer, "#ifndef BattleAnimTable")?;
+        writeln!(
+            installer,
+            "  ERROR \"BattleAnimTable must be defined as the animation table's address\""
+        )?;
+        writeln!(installer, "#endif")?;
+        writeln!(installer)?;
+        writeln!(installer, "PUSH")?;
+        writeln!(
+            installer,
+            "ORG BattleAnimTable + {:#X}",
+            self.index * ENTRY_SIZE
+        )?;
+        writeln!(installer, "{}", bytes(&name_bytes))?;
+        // Sections, script, OAM for the right and left units, palettes.
+        writeln!(
+            installer,
+            "POIN {} {}Script {} {} {}",
+            pointer(anim.sections),
+            label,
+            pointer(anim.oam_right),
+            pointer(anim.oam_left),
+            pointer(anim.palette)
+        )?;
+        writeln!(installer, "POP")?;
+        writeln!(installer)?;
+        writeln!(installer, "ALIGN 4")?;
+        writeln!(installer, "{}:", label)?;
+        writeln!(installer, "#incbin \"{}\"", data_file)?;
+        writeln!(installer)?;
+
+        // The script is the one piece that points at the rest, so it's
+        // written out here, byte by byte, with the pointers left to EA.
+        let (stream, pointers) = anim.script_stream();
+        writeln!(installer, "ALIGN 4")?;
+        writeln!(installer, "{}Script:", label)?;
+        let mut i = 0;
+        while i < stream.len() {
+            let end = pointers
+                .iter()
+                .copied()
+                .find(|&p| p >= i)
+                .unwrap_or(stream.len())
+                .min(i + 16);
+            if end > i {
+                writeln!(installer, "{}", bytes(&stream[i..end]))?;
+                i = end;
+                continue;
+            }
+
+            // A sheet pointer, as the four bytes of `POIN label+offset`.
+            let offset = u32::from_le_bytes(stream[i..i + 4].try_into()?);
+            let parts = (0..4)
+                .map(|n| {
+                    format!(
+                        "((({}+{:#X})>>{})&0xFF)",
+                        label,
+                        ROM_BASE + offset,
+                        n * 8
+                    )
+                })
+                .collect::<Vec<_>>();
+            writeln!(installer, "BYTE {}", parts.join(" "))?;
+            i += 4;
+        }
+
+        fs::write(
+            self.out_dir.join(format!("{}_installer.event", name)),
+            installer,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn bytes(data: &[u8]) -> String {
+    let bytes = data
+        .iter()
+        .map(|b| format!("{:#04X}", b))
+        .collect::<Vec<_>>();
+    format!("BYTE {}", bytes.join(" "))
+}
@@ -0,0 +1,403 @@
+// Batch conversion from a manifest.
+//
+// A manifest is a TOML file with an `[[image]]` table per image to convert.
+// Each table's keys are `convert`'s long options, e.g.
+//
+//     installer = "graphics.event"
+//
+//     [defaults]
+//     lz77 = true
+//
+//     [[image]]
+//     input = "title.png"
+//     output = "title.dmp"
+//     palette-out = "title_pal.dmp"
+//     tilemap = "title_map.dmp"
+//     repoint = "0x8B0000"
+//
+// Flags are `true` or `false`, and everything else is a string or a number.
+// Keys under `[defaults]` apply to every image that doesn't set them itself.
+// Paths are relative to the manifest, and no two images may write the same
+// file. If there's an `installer`, every image goes into it, labelled with its
+// `label` (or its input's file name).
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{ArgAction, Parser};
+use rayon::prelude::*;
+use toml::{Table, Value};
+
+use crate::{
+    gbagfx, write_installer_body, ConvertArgs, ConvertOpts, Incbin, Installer,
+    Output,
+};
+
+// Options that take a path, which we resolve relative to the manifest.
+const PATH_KEYS: &[&str] = &[
+    "input",
+    "output",
+    "palette-out",
+    "palette-in",
+    "palette-map",
+    "tilemap",
+    "diagnose",
+];
+
+// Options that only make sense for a single conversion.
+const RESERVED_KEYS: &[&str] = &["installer", "to-stdout", "help"];
+
+// Options for the installer.
+const INSTALLER_KEYS: &[&str] = &["label", "repoint", "repoint-palette"];
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct BuildArgs {
+    /// TOML manifest with an `[[image]]` table per image, whose keys are
+    /// `convert`'s long options (`input`, `output`, `palette-in`, `lz77`,
+    /// ...), plus optional `[defaults]` for every image and an `installer`
+    /// path to write one installer for everything to.
+    manifest: PathBuf,
+    /// Convert every image, even ones whose outputs are newer than their
+    /// inputs and the manifest.
+    #[arg(long, action=ArgAction::SetTrue)]
+    force: bool,
+    /// How many images to convert at once. Defaults to one per CPU.
+    #[arg(short, long)]
+    jobs: Option<usize>,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+// One image's worth of work.
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) args: Vec<OsString>,
+}
+
+enum Built {
+    Converted,
+    UpToDate,
+}
+
+// An image's part of the installer.
+type InstallerPart = (Installer, Vec<Incbin>);
+
+impl BuildArgs {
+    pub fn run(self) -> Result<()> {
+        let text = fs::read_to_string(&self.manifest)?;
+        let manifest = text
+            .parse::<Table>()
+            .map_err(|err| anyhow!("{}: {}", self.manifest.display(), err))?;
+        let dir = self.manifest.parent().unwrap_or(Path::new(""));
+
+        let mut installer_path = None;
+        let mut defaults = Table::new();
+        let mut images = &Vec::new();
+        for (key, value) in manifest.iter() {
+            match (key.as_str(), value) {
+                ("installer", Value::String(path)) => {
+                    installer_path = Some(dir.join(path))
+                }
+                ("defaults", Value::Table(table)) => defaults = table.clone(),
+                ("image", Value::Array(array)) => images = array,
+                ("installer" | "defaults" | "image", _) => {
+                    bail!("`{}` has the wrong type; see --help", key)
+                }
+                _ => bail!("unknown manifest key `{}`", key),
+            }
+        }
+        if images.is_empty() {
+            bail!("manifest has no [[image]] tables")
+        }
+
+        let entries = images
+            .iter()
+            .enumerate()
+            .map(|(i, image)| match image {
+                Value::Table(table) => {
+                    entry(i, &defaults, table, dir, installer_path.as_deref())
+                }
+                _ => bail!("image {} isn't a table", i + 1),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let count = entries.len();
+
+        let mut jobs = Vec::with_capacity(count);
+        let mut invalid = 0;
+        for entry in entries {
+            match validate(&entry) {
+                Ok(opts) => jobs.push((entry.name, opts)),
+                Err(err) => {
+                    eprintln!("{}: {}", entry.name, err);
+                    invalid += 1;
+                }
+            }
+        }
+        if invalid > 0 {
+            bail!("{} of {} images have bad options", invalid, count)
+        }
+        check_outputs(&jobs)?;
+
+        let manifest_time = modified(&self.manifest);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.jobs.unwrap_or(0))
+            .build()?;
+        let results = pool.install(|| {
+            jobs.into_par_iter()
+                .map(|(name, opts)| {
+                    let (notes, result) =
+                        build(opts, self.force, manifest_time);
+                    (name, notes, result)
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // Reported only now, so each image's notes stay together.
+        let mut failed = 0;
+        let mut converted = 0;
+        let mut installers = Vec::new();
+        for (name, notes, result) in results {
+            for note in notes {
+                eprintln!("{}: {}", name, note);
+            }
+            match result {
+                Ok((built, installer)) => {
+                    if let Built::Converted = built {
+                        converted += 1;
+                    }
+                    installers.extend(installer);
+                }
+                Err(err) => {
+                    eprintln!("{}: {}", name, err);
+                    failed += 1;
+                }
+            }
+        }
+        if failed > 0 {
+            bail!("{} of {} images failed to convert", failed, count)
+        }
+        eprintln!(
+            "converted {} images ({} up to date)",
+            converted,
+            count - converted
+        );
+
+        if let Some(path) = installer_path {
+            let mut labels = HashSet::new();
+            let mut table = String::new();
+            let mut data = String::new();
+            for (installer, incbins) in installers {
+                if !labels.insert(installer.label.clone()) {
+                    bail!(
+                        "two images are labelled `{}`; give one a `label`",
+                        installer.label
+                    )
+                }
+                let (entry_table, entry_data) = installer.render(&incbins)?;
+                table.push_str(&entry_table);
+                data.push_str(&entry_data);
+            }
+
+            let mut installer = String::new();
+            writeln!(
+                installer,
+                "// Installer for {}.",
+                self.manifest.display()
+            )?;
+            writeln!(installer)?;
+            write_installer_body(&mut installer, &table, &data)?;
+            fs::write(path, installer)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The `convert` arguments for [image], with [defaults] filled in.
+pub(crate) fn entry(
+    i: usize,
+    defaults: &Table,
+    image: &Table,
+    dir: &Path,
+    installer: Option<&Path>,
+) -> Result<Entry> {
+    let mut options = Table::new();
+    for (key, value) in defaults.iter().chain(image.iter()) {
+        options.insert(key.replace('_', "-"), value.clone());
+    }
+
+    let name = match options.get("input") {
+        Some(Value::String(input)) => input.clone(),
+        _ => bail!("image {} needs an `input` path", i + 1),
+    };
+
+    let mut args = vec![OsString::from("tilemage build")];
+    let mut input = None;
+    for (key, value) in options {
+        if RESERVED_KEYS.contains(&key.as_str()) {
+            bail!("{}: `{}` can't be set per image", name, key)
+        }
+        if installer.is_none() && INSTALLER_KEYS.contains(&key.as_str()) {
+            bail!("{}: `{}` needs an `installer` in the manifest", name, key)
+        }
+
+        let value = match value {
+            Value::Boolean(false) => continue,
+            Value::Boolean(true) => None,
+            Value::String(s) if PATH_KEYS.contains(&key.as_str()) => {
+                // `palette-in` can also be a palette written out in hex.
+                if key == "palette-in"
+                    && gbagfx::parse_palette_string(&s).is_some()
+                {
+                    Some(OsString::from(s))
+                } else {
+                    Some(dir.join(s).into_os_string())
+                }
+            }
+            Value::String(s) => Some(OsString::from(s)),
+            Value::Integer(n) => Some(OsString::from(n.to_string())),
+            _ => {
+                bail!("{}: `{}` should be a flag, string or number", name, key)
+            }
+        };
+
+        match (key.as_str(), value) {
+            ("input", value) => input = value,
+            (_, None) => args.push(format!("--{}", key).into()),
+            (_, Some(value)) => {
+                let mut arg = OsString::from(format!("--{}=", key));
+                arg.push(value);
+                args.push(arg);
+            }
+        }
+    }
+
+    if let Some(path) = installer {
+        let mut arg = OsString::from("--installer=");
+        arg.push(path);
+        args.push(arg);
+    }
+    args.push("--".into());
+    args.extend(input);
+
+    Ok(Entry { name, args })
+}
+
+// Parses and checks [entry]'s arguments as `convert` would.
+pub(crate) fn validate(entry: &Entry) -> Result<ConvertOpts> {
+    let opts = ConvertArgs::try_parse_from(&entry.args)
+        .and_then(ConvertArgs::validate)
+        .map_err(|err| {
+            // Clap's messages come with usage for the command line, which
+            // doesn't help here.
+            let message = err.to_string();
+            let message = message.split("\n\n").next().unwrap_or_default();
+            anyhow!(
+                "{}",
+                message
+                    .trim_start_matches("error: ")
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            )
+        })?;
+
+    let to_stdout = |out: &Option<Output>| matches!(out, Some(Output::Stdout));
+    if to_stdout(&opts.output) || to_stdout(&opts.palette_out) {
+        bail!("needs an `output` (or `palette-out`, with `palette-only`)")
+    }
+    Ok(opts)
+}
+
+// Fails if two images (or one image twice) would write the same file, since
+// they'd be racing each other.
+pub(crate) fn check_outputs(jobs: &[(String, ConvertOpts)]) -> Result<()> {
+    let mut writers = HashMap::new();
+    for (name, opts) in jobs {
+        for path in opts.outputs().iter().chain(opts.diagnose.iter()) {
+            let key = std::path::absolute(path)?;
+            if let Some(other) = writers.insert(key, name) {
+                bail!("{} and {} both write {}", other, name, path.display())
+            }
+        }
+    }
+    Ok(())
+}
+
+// Converts [opts] unless it's up to date, and returns what its part of the
+// installer needs, along with anything it had to report.
+fn build(
+    mut opts: ConvertOpts,
+    force: bool,
+    manifest_time: Option<SystemTime>,
+) -> (Vec<String>, Result<(Built, Option<InstallerPart>)>) {
+    let installer = opts
+        .installer
+        .take()
+        .map(|installer| (installer, opts.incbins()));
+
+    let mut notes = Vec::new();
+    if !force && opts.up_to_date(manifest_time) {
+        return (notes, Ok((Built::UpToDate, installer)));
+    }
+    let result = opts.run_noting(&mut notes);
+    (notes, result.map(|()| (Built::Converted, installer)))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl ConvertOpts {
+    // Every file a successful conversion writes.
+    fn outputs(&self) -> Vec<PathBuf> {
+        let mut outputs = Vec::new();
+        for out in [&self.output, &self.palette_out].into_iter().flatten() {
+            if let Output::File(path) = out {
+                outputs.push(path.clone());
+            }
+        }
+        outputs.extend(self.tilemap.as_ref().map(|(path, _)| path.clone()));
+        outputs.extend(self.palette_map.clone());
+        outputs
+    }
+
+    // Whether every output exists and is newer than every input (counting
+    // the manifest, since that holds the options).
+    pub(crate) fn up_to_date(&self, manifest_time: Option<SystemTime>) -> bool {
+        let mut inputs = vec![self.input.clone()];
+        if let Some(palette) = &self.palette {
+            if gbagfx::parse_palette_string(palette).is_none() {
+                inputs.push(PathBuf::from(palette));
+            }
+        }
+
+        let outputs = self.outputs();
+
+        let newest_input = inputs
+            .iter()
+            .map(|path| modified(path))
+            .chain([manifest_time])
+            .collect::<Option<Vec<_>>>()
+            .and_then(|times| times.into_iter().max());
+        let oldest_output = outputs
+            .iter()
+            .map(|path| modified(path))
+            .collect::<Option<Vec<_>>>()
+            .and_then(|times| times.into_iter().min());
+
+        match (newest_input, oldest_output) {
+            (Some(input), Some(output)) => output >= input,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use image::ImageFormat;
+
+use crate::{gbagfx, label_from_name};
+
+use gbagfx::chipset::{Chipset, MAX_METATILES};
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct ChipsetArgs {
+    /// Tileset image, made of 16x16 metatiles using at most 5 palettes.
+    input: PathBuf,
+    /// Directory to write the tileset assets and installer to.
+    #[arg(short, long, default_value = ".")]
+    out_dir: PathBuf,
+    /// Prefix for output files and installer labels. Defaults to the input
+    /// file's name.
+    #[arg(long)]
+    name: Option<String>,
+    /// Terrain type of each metatile, in order: numbers (decimal or 0x hex)
+    /// separated by whitespace or commas. `#` starts a comment. Missing
+    /// entries default to 0.
+    #[arg(long)]
+    terrain: Option<PathBuf>,
+    /// BG palette bank the game loads the palette group into.
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(0..=11))]
+    palette_base: u8,
+    /// Chapter asset ID to install the first object set half at (femaptool's
+    /// `obj1`).
+    #[arg(long)]
+    obj1_id: Option<usize>,
+    /// Chapter asset ID to install the second object set half at
+    /// (femaptool's `obj2`).
+    #[arg(long)]
+    obj2_id: Option<usize>,
+    /// Chapter asset ID to install the palette group at (femaptool's
+    /// `palette_id`).
+    #[arg(long)]
+    palette_id: Option<usize>,
+    /// Chapter asset ID to install the tile config at (femaptool's
+    /// `tileconfig`).
+    #[arg(long)]
+    tileconfig_id: Option<usize>,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl ChipsetArgs {
+    pub fn run(self) -> Result<()> {
+        let format = ImageFormat::from_path(&self.input).ok();
+        let input = fs::read(&self.input)?;
+        let image = gbagfx::decode_image(&input[..], format)?;
+        let chipset = Chipset::from_image(&image, None)?;
+
+        let terrain = match &self.terrain {
+            Some(path) => parse_terrain(&fs::read_to_string(path)?)?,
+            None => Vec::new(),
+        };
+
+        let name = match self.name {
+            Some(name) => name,
+            None => self
+                .input
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow!("can't name output; use --name"))?,
+        };
+        let label = label_from_name(&name);
+
+        let (obj1, obj2) = chipset.encode_obj();
+        if obj2.is_none() && self.obj2_id.is_some() {
+            bail!("every tile fits in obj1, so there's no obj2 to install");
+        }
+
+        let assets = [
+            ("Obj1", "obj1", Some(obj1), self.obj1_id),
+            ("Obj2", "obj2", obj2, self.obj2_id),
+            (
+                "Palette",
+                "palette",
+                Some(chipset.encode_palettes()),
+                self.palette_id,
+            ),
+            (
+                "TileConfig",
+                "tileconfig",
+                Some(
+                    chipset.encode_config(&terrain, self.palette_base as usize),
+                ),
+                self.tileconfig_id,
+            ),
+        ];
+
+        let mut data = String::new();
+        let mut table = String::new();
+        for (suffix, file_suffix, asset, id) in assets {
+            let Some(asset) = asset else { continue };
+
+            let file = format!("{}_{}.dmp", name, file_suffix);
+            fs::write(self.out_dir.join(&file), asset)?;
+
+            writeln!(data, "ALIGN 4")?;
+            writeln!(data, "{}{}:", label, suffix)?;
+            writeln!(data, "#incbin \"{}\"", file)?;
+
+            if let Some(id) = id {
+                writeln!(table, "ORG ChapterAssetTable + ({:#X} * 4)", id)?;
+                writeln!(table, "POIN {}{}", label, suffix)?;
+            }
+        }
+
+        let mut installer = String::new();
+        writeln!(installer, "// Tileset installer for {}.", name)?;
+        writeln!(installer)?;
+        if !table.is_empty() {
+            writeln!(installer, "#ifndef ChapterAssetTable")?;
+            writeln!(
+                installer,
+                "  ERROR \"ChapterAssetTable must be defined as the chapter asset table's address\""
+            )?;
+            writeln!(installer, "#endif")?;
+            writeln!(installer)?;
+            writeln!(installer, "PUSH")?;
+            installer.push_str(&table);
+            writeln!(installer, "POP")?;
+            writeln!(installer)?;
+        }
+        installer.push_str(&data);
+
+        fs::write(
+            self.out_dir.join(format!("{}_installer.event", name)),
+            installer,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn parse_terrain(s: &str) -> Result<Vec<u8>> {
+    let terrain = s
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            let parsed = match field.strip_prefix("0x") {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => field.parse(),
+            };
+            parsed.map_err(|_| anyhow!("bad terrain type `{}`", field))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if terrain.len() > MAX_METATILES {
+        bail!("more terrain types than metatiles ({})", MAX_METATILES);
+    }
+
+    Ok(terrain)
+}
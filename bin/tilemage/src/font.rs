@@ -0,0 +1,140 @@
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use anyhow::{bail, Result};
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    ArgAction, Parser,
+};
+use image::ImageFormat;
+
+use crate::{gbagfx, load_palette, parse_id};
+
+use gbagfx::font::{self, FontOptions, GlyphLayout};
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct FontArgs {
+    /// Sheet of glyphs, one per cell, read left to right, top to bottom.
+    /// Index 0 (the first color, unless --palette-in says otherwise) is the
+    /// background.
+    input: PathBuf,
+    /// Output glyph data, every glyph one after another.
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Output glyph widths, one byte per glyph.
+    #[arg(short, long)]
+    widths: Option<PathBuf>,
+    /// Output a text table of each glyph's character code, width, and offset
+    /// into the glyph data.
+    #[arg(long)]
+    table: Option<PathBuf>,
+    /// Size of each glyph's cell, in pixels (a whole number of tiles).
+    #[arg(long, default_value = "16x16", value_parser = parse_cell_size)]
+    cell: (usize, usize),
+    /// Character code of the first glyph. The rest follow in order.
+    #[arg(long, default_value = "0x20", value_parser = parse_id)]
+    first: usize,
+    /// Bits per pixel of the glyph data.
+    #[arg(
+        long,
+        default_value = "2",
+        value_parser = PossibleValuesParser::new(["1", "2"])
+            .map(|s| s.parse::<u8>().unwrap()),
+    )]
+    bpp: u8,
+    /// Store each glyph as 8x8 tiles, rather than a row at a time like FE's
+    /// text glyphs.
+    #[arg(long, action=ArgAction::SetTrue)]
+    tiles: bool,
+    /// Blank columns to leave after each glyph.
+    #[arg(long, default_value_t = 1)]
+    spacing: usize,
+    /// Width of glyphs with nothing drawn in them, like the space.
+    #[arg(long, default_value_t = 4)]
+    blank_width: usize,
+    /// Use the specified palette instead of the input image's.
+    #[arg(long)]
+    palette_in: Option<String>,
+    #[arg(short = 'p', long)]
+    palette_out: Option<PathBuf>,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl FontArgs {
+    pub fn run(self) -> Result<()> {
+        let format = ImageFormat::from_path(&self.input).ok();
+        let input = fs::read(&self.input)?;
+
+        let palette = match self.palette_in {
+            Some(s) => Some(load_palette(s)?),
+            None => None,
+        };
+
+        let opts = FontOptions {
+            cell_width: self.cell.0,
+            cell_height: self.cell.1,
+            first: self.first as u32,
+            depth: match self.bpp {
+                1 => gbagfx::BitDepth::One,
+                _ => gbagfx::BitDepth::Two,
+            },
+            layout: if self.tiles {
+                GlyphLayout::Tiles
+            } else {
+                GlyphLayout::Rows
+            },
+            spacing: self.spacing,
+            blank_width: self.blank_width,
+        };
+
+        let sheet = gbagfx::convert_image(&input[..], format, palette)?;
+        let glyphs = font::glyphs(&sheet, &opts)?;
+
+        let mut data = Vec::new();
+        let mut widths = Vec::new();
+        let mut table = String::new();
+        writeln!(table, "# code width offset")?;
+        for glyph in glyphs.iter() {
+            if glyph.width > 0xFF {
+                bail!(
+                    "glyph {:#X} is too wide ({} pixels)",
+                    glyph.code,
+                    glyph.width
+                )
+            }
+            writeln!(
+                table,
+                "{:#04X} {} {:#X}",
+                glyph.code,
+                glyph.width,
+                data.len()
+            )?;
+            widths.push(glyph.width as u8);
+            data.extend(glyph.encode(opts.depth, opts.layout));
+        }
+
+        fs::write(&self.output, data)?;
+        if let Some(path) = self.widths {
+            fs::write(path, widths)?;
+        }
+        if let Some(path) = self.table {
+            fs::write(path, table)?;
+        }
+        if let Some(path) = self.palette_out {
+            fs::write(path, sheet.palette.encode())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_cell_size(s: &str) -> Result<(usize, usize), String> {
+    s.to_lowercase()
+        .split_once('x')
+        .and_then(|(w, h)| {
+            Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+        })
+        .ok_or_else(|| "expected WIDTHxHEIGHT (in pixels)".to_string())
+}
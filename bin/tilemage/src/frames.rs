@@ -0,0 +1,112 @@
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{ArgAction, Parser};
+use image::ImageFormat;
+
+use crate::{gbagfx, load_palette, maybe_compress};
+
+use gbagfx::frames;
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct FramesArgs {
+    /// Animated GIF or APNG. Every frame must be the same size, a whole
+    /// number of tiles.
+    input: PathBuf,
+    /// Output tile data, every frame's tiles one after another.
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Write each frame to its own numbered file instead, e.g. `walk.dmp`
+    /// becomes `walk_000.dmp`, `walk_001.dmp`, ...
+    #[arg(long, action=ArgAction::SetTrue)]
+    split: bool,
+    /// Output frame durations, one line per frame, in milliseconds and in
+    /// GBA frames (1/60 s). Defaults to the output with a `.durations`
+    /// extension.
+    #[arg(long)]
+    durations: Option<PathBuf>,
+    #[arg(short = 'p', long)]
+    palette_out: Option<PathBuf>,
+    /// Use the specified palette instead of deriving one from all frames.
+    #[arg(long)]
+    palette_in: Option<String>,
+    /// Compress tile data (each file separately, with --split).
+    #[arg(long, action=ArgAction::SetTrue)]
+    lz77: bool,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl FramesArgs {
+    pub fn run(self) -> Result<()> {
+        let format = ImageFormat::from_path(&self.input).ok();
+        let input = fs::read(&self.input)?;
+
+        let palette = match self.palette_in {
+            Some(s) => Some(load_palette(s)?),
+            None => None,
+        };
+
+        let frames = frames::decode_frames(&input[..], format)?;
+        // Animations are almost always drawn over a transparent background,
+        // which has to end up at index 0.
+        let sheet = frames::convert_frames(
+            &frames,
+            &gbagfx::ConvertOptions {
+                palette,
+                transparency: Some(Default::default()),
+                ..Default::default()
+            },
+        )?;
+        sheet.validate()?;
+
+        let tiles = gbagfx::encode_tiles(sheet.tiles());
+        if self.split {
+            let stem = self
+                .output
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow!("can't name frame files"))?;
+            let frame_size = tiles.len() / frames.len();
+            if frame_size == 0 {
+                bail!("frames are empty, so there's nothing to split");
+            }
+            for (i, frame) in tiles.chunks(frame_size).enumerate() {
+                let mut name = format!("{}_{:03}", stem, i);
+                if let Some(ext) = self.output.extension() {
+                    name = format!("{}.{}", name, ext.to_string_lossy());
+                }
+                fs::write(
+                    self.output.with_file_name(name),
+                    maybe_compress(self.lz77, frame.to_vec()),
+                )?;
+            }
+        } else {
+            fs::write(&self.output, maybe_compress(self.lz77, tiles))?;
+        }
+
+        let mut durations = String::new();
+        writeln!(durations, "# frame milliseconds gba_frames")?;
+        for (i, frame) in frames.iter().enumerate() {
+            writeln!(
+                durations,
+                "{} {} {}",
+                i,
+                frame.delay_ms,
+                frame.delay_frames()
+            )?;
+        }
+        let durations_path = self
+            .durations
+            .unwrap_or_else(|| self.output.with_extension("durations"));
+        fs::write(durations_path, durations)?;
+
+        if let Some(path) = self.palette_out {
+            fs::write(path, sheet.palette.encode())?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,150 @@
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use clap::Parser;
+use image::ImageFormat;
+
+use crate::{gbagfx, load_palette, parse_id};
+
+use gbagfx::icons::{self, ICON_BYTES};
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct IconsArgs {
+    /// Sheet of 16x16 icons, read left to right, top to bottom.
+    input: PathBuf,
+    /// Directory to write the icons and installer to.
+    #[arg(short, long, default_value = ".")]
+    out_dir: PathBuf,
+    /// Prefix for output files. Defaults to the input file's name.
+    #[arg(long)]
+    name: Option<String>,
+    /// Icon ID of each icon on the sheet, in order, as a comma-separated
+    /// list.
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_id,
+        required_unless_present = "first_id",
+        conflicts_with = "first_id"
+    )]
+    ids: Vec<usize>,
+    /// Give the icons consecutive IDs starting from this one.
+    #[arg(long, value_parser = parse_id)]
+    first_id: Option<usize>,
+    /// Use the specified palette instead of the input image's. Icons using
+    /// any other color are rejected.
+    #[arg(long)]
+    palette_in: Option<String>,
+    /// Output the shared palette.
+    #[arg(short = 'p', long)]
+    palette_out: Option<PathBuf>,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl IconsArgs {
+    pub fn run(self) -> Result<()> {
+        let format = ImageFormat::from_path(&self.input).ok();
+        let input = fs::read(&self.input)?;
+
+        let palette = match self.palette_in {
+            Some(s) => Some(load_palette(s)?),
+            None => None,
+        };
+
+        // Inferring the palette at 8bpp lets a 17th color through, so
+        // compacting can say which icon brought it in. A given palette keeps
+        // its order, and only offers its first 16 colors.
+        let compact = palette.is_none();
+        let depth = match palette {
+            Some(_) => gbagfx::BitDepth::Four,
+            None => gbagfx::BitDepth::Eight,
+        };
+        let mut sheet = gbagfx::convert_image_with_options(
+            &input[..],
+            format,
+            gbagfx::ConvertOptions {
+                palette,
+                depth,
+                ..Default::default()
+            },
+        )?;
+        if compact {
+            icons::compact(&mut sheet)?;
+        }
+        let mut icons = icons::icons(&sheet)?;
+
+        // Blank cells at the end are only padding out the sheet's grid,
+        // unless every icon was given an ID.
+        let blank = icons
+            .iter()
+            .rev()
+            .take_while(|icon| icons::is_blank(icon))
+            .count();
+        if blank > 0 && self.ids.len() != icons.len() {
+            icons.truncate(icons.len() - blank);
+            eprintln!(
+                "skipping {} blank icon(s) at the end of the sheet",
+                blank
+            );
+        }
+
+        let ids = match self.first_id {
+            Some(first) => (first..first + icons.len()).collect(),
+            None => self.ids,
+        };
+        if ids.len() != icons.len() {
+            bail!(
+                "the sheet has {} icons, but {} IDs were given",
+                icons.len(),
+                ids.len()
+            );
+        }
+
+        let name = match self.name {
+            Some(name) => name,
+            None => self
+                .input
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow!("can't name output; use --name"))?,
+        };
+
+        let mut installer = String::new();
+        writeln!(installer, "// Icon installer for {}.", name)?;
+        writeln!(installer)?;
+        writeln!(installer, "#ifndef IconTable")?;
+        writeln!(
+            installer,
+            "  ERROR \"IconTable must be defined as the icon graphics table's address\""
+        )?;
+        writeln!(installer, "#endif")?;
+        writeln!(installer)?;
+        writeln!(installer, "PUSH")?;
+
+        for (icon, id) in icons.iter().zip(ids) {
+            let file = format!("{}_{:03X}.dmp", name, id);
+            fs::write(self.out_dir.join(&file), icons::encode_icon(icon))?;
+
+            writeln!(installer, "ORG IconTable + {:#X}", id * ICON_BYTES)?;
+            writeln!(installer, "#incbin \"{}\"", file)?;
+        }
+
+        writeln!(installer, "POP")?;
+
+        fs::write(
+            self.out_dir.join(format!("{}_installer.event", name)),
+            installer,
+        )?;
+
+        if let Some(path) = self.palette_out {
+            let mut palette = sheet.palette.encode();
+            palette.truncate(16 * 2);
+            fs::write(path, palette)?;
+        }
+
+        Ok(())
+    }
+}
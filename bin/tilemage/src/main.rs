@@ -1,22 +1,66 @@
 // XXX: This is the world's most overengineered argument parser.
 
-use std::{env, fs, io::Write, path::PathBuf};
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{bail, Result};
 use atty;
 use clap::{
+    builder::RangedU64ValueParser,
     error::{ContextKind, ContextValue, DefaultFormatter, Error, ErrorKind},
-    ArgAction, Command, CommandFactory, Parser, Subcommand,
+    ArgAction, Command, CommandFactory, Parser, Subcommand, ValueEnum,
 };
 use image::{ImageFormat, ImageReader};
 
 use gbalz77 as lz77;
 use tilemage as gbagfx;
 
+use gbagfx::arrange::{Arrangement, TileRect};
+use gbagfx::palfile::PaletteFormat;
+
+mod banim;
+mod build;
+mod chipset;
+mod font;
+mod frames;
+mod icons;
+mod obj;
+mod palanim;
+mod portrait;
+mod unconvert;
+
+#[cfg(test)]
+mod tests;
+
 #[derive(Subcommand, Debug)]
 enum Mode {
     /// Direct conversion to GBA format.
     Convert(ConvertArgs),
+    /// Convert every image listed in a manifest.
+    Build(build::BuildArgs),
+    /// Render GBA tile data back to an indexed PNG.
+    Unconvert(unconvert::UnconvertArgs),
+    /// Convert every frame of an animated GIF or APNG with one palette.
+    Frames(frames::FramesArgs),
+    /// Split a sprite into OAM pieces.
+    Obj(obj::ObjArgs),
+    /// Format a Fire Emblem portrait sheet for insertion.
+    Portrait(portrait::PortraitArgs),
+    /// Compile a Fire Emblem battle animation script.
+    Banim(banim::BanimArgs),
+    /// Build a Fire Emblem map tileset from a metatile sheet.
+    Chipset(chipset::ChipsetArgs),
+    /// Slice a sheet of Fire Emblem item/skill icons.
+    Icons(icons::IconsArgs),
+    /// Encode a sheet of font glyphs at 1bpp or 2bpp, with their widths.
+    Font(font::FontArgs),
+    /// Generate palette frames fading a palette or cycling its colors.
+    PaletteAnim(palanim::PaletteAnimArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -27,9 +71,18 @@ struct ConvertArgs {
     output: Option<PathBuf>,
     #[arg(short = 'p', long)]
     palette_out: Option<PathBuf>,
+    /// Format to write the palette in. Defaults to guessing from the
+    /// extension of the palette output, or raw GBA colors if that fails.
+    #[arg(long, value_enum, requires = "palette_out")]
+    palette_format: Option<PaletteFormatArg>,
     /// Use the specified palette instead of the input image's.
     #[arg(long)]
     palette_in: Option<String>,
+    /// With --palette-in, move colors that aren't in the palette onto the
+    /// entry the GBA displays identically, or else the nearest entry with no
+    /// channel more than this many 5-bit steps (default 2) away.
+    #[arg(long, requires = "palette_in", num_args = 0..=1, default_missing_value = "2")]
+    remap: Option<u8>,
     /// Write to stdout. Mutually exclusive with other output options.
     #[arg(long, action=ArgAction::SetTrue)]
     to_stdout: bool,
@@ -39,11 +92,144 @@ struct ConvertArgs {
     /// Compress result
     #[arg(long, action=ArgAction::SetTrue)]
     lz77: bool,
+    /// Split the image across up to this many 16-color palettes, chosen
+    /// per-tile (for text-mode backgrounds).
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=16))]
+    palettes: Option<u8>,
+    /// With --palettes, write the palette bank used by each tile (one byte
+    /// per tile, row-major) to this file.
+    #[arg(long, requires = "palettes")]
+    palette_map: Option<PathBuf>,
+    /// Order to write tiles in: `row` (the default), `column`, `metatile`
+    /// (16x16 blocks), `WxH` blocks of tiles, or a list of tile rectangles
+    /// `X,Y,W,H;X,Y,W,H;...` to write one after another.
+    #[arg(long, value_parser = parse_arrangement, conflicts_with = "tilemap")]
+    arrangement: Option<Arrangement>,
+    /// Deduplicate tiles and write a text BG tilemap to this file. The tile
+    /// output will then contain only the unique tiles.
+    #[arg(long)]
+    tilemap: Option<PathBuf>,
+    /// Size of the tilemap, in tiles (e.g. `32x32`). Defaults to the size of
+    /// the image.
+    #[arg(long, requires = "tilemap", value_parser = parse_map_size)]
+    map_size: Option<(usize, usize)>,
+    /// Don't reuse tiles that are flipped copies of each other.
+    #[arg(long, requires = "tilemap", action=ArgAction::SetTrue)]
+    no_flips: bool,
+    /// Write 8bpp tiles and an affine BG tilemap (one byte per entry, no
+    /// flips) instead. The map size must be square: 16, 32, 64 or 128 tiles.
+    #[arg(long, requires = "tilemap", conflicts_with_all = ["palettes", "no_flips"], action=ArgAction::SetTrue)]
+    affine: bool,
+    /// Reduce the image to at most this many colors (default 16) instead of
+    /// failing when it has too many. Up to 256 with --affine, or 16
+    /// otherwise.
+    #[arg(
+        long,
+        num_args = 0..=1,
+        default_missing_value = "16",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..=256)
+    )]
+    reduce_colors: Option<usize>,
+    /// Dithering to use with --reduce-colors.
+    #[arg(long, requires = "reduce_colors", value_enum, default_value_t)]
+    dither: DitherArg,
+    /// Pixels with alpha at or below this are transparent, and are given
+    /// palette index 0.
+    #[arg(long, default_value_t = 0)]
+    alpha_threshold: u8,
+    /// Color to store at index 0 for transparent pixels, as RRGGBB. Defaults
+    /// to the color of the first transparent pixel.
+    #[arg(long, value_parser = parse_rgb)]
+    backdrop: Option<gbagfx::Color>,
+    /// Treat every pixel as opaque, whatever its alpha.
+    #[arg(long, action=ArgAction::SetTrue, conflicts_with_all = ["alpha_threshold", "backdrop"])]
+    ignore_alpha: bool,
+    /// If the image has too many or unknown colors, or a bad size, write a
+    /// copy of it here with the offending tiles and pixels highlighted.
+    #[arg(long)]
+    diagnose: Option<PathBuf>,
+    /// How to show the GBA's colors in the --diagnose copy, as in
+    /// `unconvert --preview`.
+    #[arg(long, value_enum, default_value_t, requires = "diagnose")]
+    preview: PreviewArg,
+    /// Write an Event Assembler installer that `#incbin`s the output files
+    /// here. Needs --output rather than stdout. Outputs that aren't
+    /// compressed also get a `<LABEL>Size` define.
+    #[arg(long)]
+    installer: Option<PathBuf>,
+    /// Label for the graphics in the installer. The palette and tilemap get
+    /// `<LABEL>Palette` and `<LABEL>Map`. Defaults to the input's file name.
+    #[arg(long, requires = "installer")]
+    label: Option<String>,
+    /// Have the installer overwrite the pointer at this address (e.g.
+    /// `0x8B0000` or `PortraitTable + 0x1C`) to point at the graphics.
+    #[arg(long, requires = "installer")]
+    repoint: Option<String>,
+    /// Likewise for the palette.
+    #[arg(long, requires_all = ["installer", "palette_out"])]
+    repoint_palette: Option<String>,
     /// Print help information
     #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
     help: Option<bool>,
 }
 
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum DitherArg {
+    #[default]
+    None,
+    FloydSteinberg,
+    Ordered,
+}
+
+impl From<DitherArg> for gbagfx::quantize::Dither {
+    fn from(d: DitherArg) -> Self {
+        match d {
+            DitherArg::None => Self::None,
+            DitherArg::FloydSteinberg => Self::FloydSteinberg,
+            DitherArg::Ordered => Self::Ordered,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum PreviewArg {
+    #[default]
+    Raw,
+    Expanded,
+    Lcd,
+}
+
+impl From<PreviewArg> for gbagfx::Preview {
+    fn from(p: PreviewArg) -> Self {
+        match p {
+            PreviewArg::Raw => Self::Raw,
+            PreviewArg::Expanded => Self::Expanded,
+            PreviewArg::Lcd => Self::Lcd,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PaletteFormatArg {
+    Raw,
+    Hex,
+    Jasc,
+    Gimp,
+    Act,
+}
+
+impl From<PaletteFormatArg> for PaletteFormat {
+    fn from(f: PaletteFormatArg) -> Self {
+        match f {
+            PaletteFormatArg::Raw => Self::Raw,
+            PaletteFormatArg::Hex => Self::Hex,
+            PaletteFormatArg::Jasc => Self::JascPal,
+            PaletteFormatArg::Gimp => Self::Gimp,
+            PaletteFormatArg::Act => Self::Act,
+        }
+    }
+}
+
 enum Output {
     Stdout,
     File(PathBuf),
@@ -53,42 +239,215 @@ enum Output {
 struct ConvertOpts {
     input: PathBuf,
     palette: Option<String>,
+    remap: Option<gbagfx::Remap>,
     output: Option<Output>,
     palette_out: Option<Output>,
+    palette_format: Option<PaletteFormat>,
     force_stdout: bool,
     lz77: bool,
+    palettes: Option<usize>,
+    palette_map: Option<PathBuf>,
+    arrangement: Arrangement,
+    tilemap: Option<(PathBuf, gbagfx::bg::TilemapOptions)>,
+    affine: bool,
+    reduce_colors: Option<gbagfx::quantize::QuantizeOptions>,
+    transparency: Option<gbagfx::Transparency>,
+    diagnose: Option<PathBuf>,
+    preview: gbagfx::Preview,
+    installer: Option<Installer>,
+}
+
+// Where to write an installer for the output files, and what to call them.
+struct Installer {
+    path: PathBuf,
+    label: String,
+    repoint: Option<String>,
+    repoint_palette: Option<String>,
+}
+
+impl Installer {
+    fn write(&self, input: &Path, incbins: &[Incbin]) -> Result<()> {
+        let (table, data) = self.render(incbins)?;
+
+        let mut installer = String::new();
+        writeln!(installer, "// Installer for {}.", input.display())?;
+        writeln!(installer)?;
+        write_installer_body(&mut installer, &table, &data)?;
+
+        fs::write(&self.path, installer)?;
+        Ok(())
+    }
+
+    // The lines repointing table entries, and the lines including the data
+    // itself.
+    fn render(&self, incbins: &[Incbin]) -> Result<(String, String)> {
+        let mut table = String::new();
+        let mut data = String::new();
+        for incbin in incbins {
+            let label = format!("{}{}", self.label, incbin.suffix);
+            let repoint = match incbin.suffix {
+                "" => &self.repoint,
+                "Palette" => &self.repoint_palette,
+                _ => &None,
+            };
+            if let Some(addr) = repoint {
+                writeln!(table, "ORG {}", addr)?;
+                writeln!(table, "POIN {}", label)?;
+            }
+
+            // Compressed data starts with its decompressed size, but the game
+            // has to be told how much of anything else to copy.
+            if !incbin.compressed {
+                let size = fs::metadata(&incbin.file)?.len();
+                writeln!(data, "#define {}Size {:#X}", label, size)?;
+            }
+            writeln!(data, "ALIGN 4")?;
+            writeln!(data, "{}:", label)?;
+            writeln!(
+                data,
+                "#incbin \"{}\"",
+                self.relative_path(&incbin.file)?
+            )?;
+        }
+        Ok((table, data))
+    }
+
+    // [file] as the installer should #incbin it. Event Assembler resolves
+    // paths from the installer's directory, not ours.
+    fn relative_path(&self, file: &Path) -> Result<String> {
+        let file = std::path::absolute(file)?;
+        let installer = std::path::absolute(&self.path)?;
+        let dir = installer.parent().unwrap_or(Path::new(""));
+
+        // Climb out of the installer's directory until it contains the file.
+        // (On Windows, a file on another drive never will.)
+        let mut path = PathBuf::new();
+        for ancestor in dir.ancestors() {
+            if let Ok(rest) = file.strip_prefix(ancestor) {
+                path.push(rest);
+                return Ok(path.to_string_lossy().replace('\\', "/"));
+            }
+            path.push("..");
+        }
+        Ok(file.to_string_lossy().replace('\\', "/"))
+    }
+}
+
+// An output file for an installer to #incbin, labelled `<label><suffix>`.
+struct Incbin {
+    suffix: &'static str,
+    file: PathBuf,
+    compressed: bool,
+}
+
+fn write_installer_body(
+    installer: &mut String,
+    table: &str,
+    data: &str,
+) -> Result<()> {
+    if !table.is_empty() {
+        writeln!(installer, "PUSH")?;
+        installer.push_str(table);
+        writeln!(installer, "POP")?;
+        writeln!(installer)?;
+    }
+    installer.push_str(data);
+    Ok(())
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && s.chars().next().is_some_and(|c| !c.is_ascii_digit())
+}
+
+// A label made from [path]'s file name; see [label_from_name].
+fn label_from_path(path: &Path) -> String {
+    label_from_name(
+        &path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default(),
+    )
+}
+
+// A label made from [name], minus anything EA wouldn't accept.
+fn label_from_name(name: &str) -> String {
+    let label = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if is_identifier(&label) {
+        label
+    } else {
+        format!("_{}", label)
+    }
 }
 
 impl ConvertArgs {
-    fn validate(self) -> Result<ConvertOpts> {
+    fn validate(self) -> Result<ConvertOpts, Error> {
         use Output::*;
 
         let mut cmd = ConvertArgs::command();
         let force_stdout = self.to_stdout;
 
+        if self.palettes.is_some() && self.palette_in.is_some() {
+            return Err(cmd.error(
+                ErrorKind::ArgumentConflict,
+                "--palettes and --palette-in are mutually exclusive",
+            ));
+        }
+
+        if self.reduce_colors.is_some()
+            && (self.palettes.is_some() || self.palette_in.is_some())
+        {
+            return Err(cmd.error(
+                ErrorKind::ArgumentConflict,
+                "--reduce-colors can't be used with --palettes or --palette-in",
+            ));
+        }
+
+        match self.reduce_colors {
+            Some(colors) if !self.affine && colors > 16 => {
+                return Err(cmd.error(
+                    ErrorKind::ValueValidation,
+                    "--reduce-colors can't be more than 16 without --affine",
+                ));
+            }
+            _ => (),
+        }
+
+        if let (true, Some((width, height))) = (self.affine, self.map_size) {
+            if width != height || !gbagfx::affine::SIZES.contains(&width) {
+                return Err(cmd.error(
+                    ErrorKind::ValueValidation,
+                    "affine maps must be 16x16, 32x32, 64x64 or 128x128 tiles",
+                ));
+            }
+        }
+
         let (output, palette_out) = if self.palette_only {
             let palette_out = match (self.output, self.palette_out) {
                 (None, None) => Some(Stdout),
                 (Some(p), None) | (None, Some(p)) => {
                     if force_stdout {
-                        cmd.error(ErrorKind::ValueValidation,
+                        return Err(cmd.error(ErrorKind::ValueValidation,
                             "--output/--palette-out and --to-stdout are mutually exclusive"
-                            ).exit()
+                            ))
                     }
                     Some(File(p))
                 }
                 (Some(_), Some(_)) => {
-                    cmd.error(ErrorKind::ValueValidation,
-                        "--palette-only can only be used with at most one of --output or --palette-out").exit()
+                    return Err(cmd.error(ErrorKind::ValueValidation,
+                        "--palette-only can only be used with at most one of --output or --palette-out"))
                 }
             };
             (None, palette_out)
         } else {
             let output = match (self.output, force_stdout) {
                 (Some(_), true) =>
-                        cmd.error(ErrorKind::ValueValidation,
+                        return Err(cmd.error(ErrorKind::ValueValidation,
                             "--output/--palette-out and --to-stdout are mutually exclusive"
-                            ).exit(),
+                            )),
                 (Some(fname), false) => Some(File(fname)),
                 (None, _) => Some(Stdout),
             };
@@ -97,13 +456,89 @@ impl ConvertArgs {
             (output, palette_out)
         };
 
+        let installer = match self.installer {
+            None => None,
+            Some(path) => {
+                let to_stdout =
+                    |out: &Option<Output>| matches!(out, Some(Stdout));
+                if to_stdout(&output) || to_stdout(&palette_out) {
+                    return Err(cmd.error(
+                        ErrorKind::ArgumentConflict,
+                        "--installer needs --output, since it can't #incbin stdout",
+                    ));
+                }
+                if let Some(target) = &palette_out {
+                    let format = self.palette_format.map(PaletteFormat::from);
+                    if palette_out_format(format, target) != PaletteFormat::Raw
+                    {
+                        return Err(cmd.error(
+                            ErrorKind::ArgumentConflict,
+                            "--installer needs the palette in raw GBA format",
+                        ));
+                    }
+                }
+
+                let label = match self.label {
+                    Some(label) if is_identifier(&label) => label,
+                    Some(label) => {
+                        return Err(cmd.error(
+                            ErrorKind::ValueValidation,
+                            format!("`{}` isn't a valid label", label),
+                        ))
+                    }
+                    None => label_from_path(&self.input),
+                };
+                Some(Installer {
+                    path,
+                    label,
+                    repoint: self.repoint,
+                    repoint_palette: self.repoint_palette,
+                })
+            }
+        };
+
+        let transparency = if self.ignore_alpha {
+            None
+        } else {
+            Some(gbagfx::Transparency {
+                alpha_threshold: self.alpha_threshold,
+                backdrop: self.backdrop,
+            })
+        };
+
         Ok(ConvertOpts {
             input: self.input,
             palette: self.palette_in,
+            remap: self.remap.map(|tolerance| gbagfx::Remap { tolerance }),
             output,
             palette_out,
+            palette_format: self.palette_format.map(PaletteFormat::from),
             force_stdout,
             lz77: self.lz77,
+            palettes: self.palettes.map(usize::from),
+            palette_map: self.palette_map,
+            arrangement: self.arrangement.unwrap_or_default(),
+            tilemap: self.tilemap.map(|path| {
+                (
+                    path,
+                    gbagfx::bg::TilemapOptions {
+                        flips: !self.no_flips,
+                        size: self.map_size,
+                    },
+                )
+            }),
+            affine: self.affine,
+            reduce_colors: self.reduce_colors.map(|colors| {
+                gbagfx::quantize::QuantizeOptions {
+                    colors,
+                    dither: self.dither.into(),
+                    transparency,
+                }
+            }),
+            transparency,
+            diagnose: self.diagnose,
+            preview: self.preview.into(),
+            installer,
         })
     }
 }
@@ -212,10 +647,30 @@ fn legacy_argparse(args: &[String]) -> Result<Args> {
                 input,
                 output,
                 palette_out,
+                palette_format: None,
                 palette_in,
+                remap: None,
                 to_stdout,
                 palette_only,
                 lz77,
+                palettes: None,
+                palette_map: None,
+                arrangement: None,
+                tilemap: None,
+                map_size: None,
+                no_flips: false,
+                affine: false,
+                reduce_colors: None,
+                dither: DitherArg::None,
+                alpha_threshold: 0,
+                backdrop: None,
+                ignore_alpha: false,
+                diagnose: None,
+                preview: PreviewArg::Raw,
+                installer: None,
+                label: None,
+                repoint: None,
+                repoint_palette: None,
                 help,
             }),
         })
@@ -236,18 +691,97 @@ fn load_palette(s: impl AsRef<str>) -> Result<gbagfx::Palette> {
         None => (),
     }
 
-    if vec![".dmp", ".bin", ".pal"]
-        .iter()
-        .any(|suffix| s.as_ref().to_lowercase().ends_with(suffix))
-    {
+    if PaletteFormat::from_path(s.as_ref()).is_some() {
         let data = fs::read(s.as_ref())?;
-        Ok(data.into_iter().collect())
+        // `from_path` succeeding means `sniff` will too.
+        let format = PaletteFormat::sniff(s.as_ref(), &data[..]).unwrap();
+        Ok(gbagfx::Palette::read(&data[..], format)?)
     } else {
         let image = ImageReader::open(s.as_ref())?.decode()?;
-        Ok(gbagfx::read_colors_from_image(&image))
+        Ok(gbagfx::read_colors_from_image(&gbagfx::to_8bit(image)))
     }
 }
 
+fn parse_map_size(s: &str) -> Result<(usize, usize), String> {
+    let (width, height) = s
+        .to_lowercase()
+        .split_once('x')
+        .map(|(w, h)| (w.trim().parse::<usize>(), h.trim().parse::<usize>()))
+        .ok_or_else(|| "expected WIDTHxHEIGHT (in tiles)".to_string())?;
+
+    match (width, height) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err("expected WIDTHxHEIGHT (in tiles)".to_string()),
+    }
+}
+
+fn parse_arrangement(s: &str) -> Result<Arrangement, String> {
+    match s.to_lowercase().as_str() {
+        "row" => return Ok(Arrangement::RowMajor),
+        "column" => return Ok(Arrangement::ColumnMajor),
+        "metatile" => return Ok(Arrangement::metatiles()),
+        _ => (),
+    }
+
+    if !s.contains(',') {
+        let (width, height) = parse_map_size(s)?;
+        return Ok(Arrangement::Blocks { width, height });
+    }
+
+    s.split(';')
+        .filter(|rect| !rect.trim().is_empty())
+        .map(|rect| {
+            match rect
+                .split(',')
+                .map(|n| n.trim().parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .as_deref()
+            {
+                Ok(&[x, y, width, height]) => {
+                    Ok(TileRect::new(x, y, width, height))
+                }
+                _ => Err(format!("expected X,Y,WIDTH,HEIGHT, got `{}`", rect)),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Arrangement::Regions)
+}
+
+// Table indices and IDs are often written in hex, so accept either.
+fn parse_id(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("expected a number, got `{}`", s))
+}
+
+fn parse_rgb(s: &str) -> Result<gbagfx::Color, String> {
+    let s = s.trim_start_matches('#');
+    match u32::from_str_radix(s, 16) {
+        Ok(rgb) if s.len() == 6 => Ok(gbagfx::Color::rgb(
+            (rgb >> 16) as u8,
+            (rgb >> 8) as u8,
+            rgb as u8,
+        )),
+        _ => Err("expected a color as RRGGBB".to_string()),
+    }
+}
+
+// The format to write a palette to [target] in, unless told otherwise.
+fn palette_out_format(
+    format: Option<PaletteFormat>,
+    target: &Output,
+) -> PaletteFormat {
+    format
+        .or(match target {
+            Output::File(path) => PaletteFormat::from_path(path),
+            Output::Stdout => None,
+        })
+        .unwrap_or(PaletteFormat::Raw)
+}
+
 fn maybe_compress(lz77: bool, data: Vec<u8>) -> Vec<u8> {
     if lz77 {
         lz77::compress(&data[..], lz77::CompressionStrategy::CheckAllCandidates)
@@ -256,6 +790,49 @@ fn maybe_compress(lz77: bool, data: Vec<u8>) -> Vec<u8> {
     }
 }
 
+#[derive(thiserror::Error, Debug)]
+enum DecompressError {
+    #[error("bad reference in block {i} (tried to reference index {offs})")]
+    BadReference { i: usize, offs: usize },
+    #[error("input is not long enough to be valid lz77")]
+    DataTooShort,
+    #[error("invalid header (gbalz77 data must begin with 0x10)")]
+    BadHeader,
+    #[error("input data is incomplete (got eof, expected {expected})")]
+    UnexpectedEof { expected: &'static str },
+}
+
+impl lz77::BadBlockErrorHandler for DecompressError {
+    fn bad_reference(i: usize, offs: usize) -> Self {
+        Self::BadReference { i, offs }
+    }
+}
+
+impl lz77::DecompressErrorHandler for DecompressError {
+    fn data_too_short() -> Self {
+        Self::DataTooShort
+    }
+    fn bad_header() -> Self {
+        Self::BadHeader
+    }
+    fn unexpected_eof(expected: &'static str) -> Self {
+        Self::UnexpectedEof { expected }
+    }
+}
+
+fn maybe_decompress(lz77: bool, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !lz77 {
+        return Ok(data);
+    }
+
+    let (result, errs) = lz77::decompress::<DecompressError>(&data[..]);
+    if let Some(err) = errs.into_iter().next() {
+        bail!("bad lz77 data: {err}")
+    }
+
+    Ok(result)
+}
+
 fn write_target(
     target: Output,
     data: Vec<u8>,
@@ -286,9 +863,77 @@ fn try_write_stdout(data: Vec<u8>, force: bool) -> Result<()> {
 
 impl ConvertOpts {
     fn run(self) -> Result<()> {
+        let mut notes = Vec::new();
+        let result = self.run_noting(&mut notes);
+        for note in notes {
+            eprintln!("{}", note);
+        }
+        result
+    }
+
+    // Like [ConvertOpts::run], but collects what it would report in
+    // [notes], for callers running several conversions at once.
+    fn run_noting(mut self, notes: &mut Vec<String>) -> Result<()> {
         let format = ImageFormat::from_path(&self.input).ok();
-        let input = fs::read(self.input)?;
+        let input = fs::read(&self.input)?;
+        let diagnose = self.diagnose.take();
+        let preview = self.preview;
+        let installer = self
+            .installer
+            .take()
+            .map(|installer| (self.incbins(), installer));
+        let path = self.input.clone();
+
+        let result = self.convert(&input, format, notes);
+        if let (Ok(()), Some((incbins, installer))) = (&result, installer) {
+            installer.write(&path, &incbins)?;
+        }
+        if let (Err(err), Some(path)) = (&result, diagnose) {
+            let diagnosis = err
+                .downcast_ref::<gbagfx::Error>()
+                .and_then(|err| err.diagnosis());
+            if let Some(diagnosis) = diagnosis {
+                let image = gbagfx::decode_image(&input[..], format)?;
+                diagnosis.render(&image, preview).save(&path)?;
+                notes.push(format!("wrote diagnostics to {}", path.display()));
+            }
+        }
+        result
+    }
+
+    // The files an installer should #incbin, in the order to include them.
+    fn incbins(&self) -> Vec<Incbin> {
+        let mut incbins = Vec::new();
+        if let Some(Output::File(path)) = &self.output {
+            incbins.push(Incbin {
+                suffix: "",
+                file: path.clone(),
+                compressed: self.lz77,
+            });
+        }
+        if let Some((path, _)) = &self.tilemap {
+            incbins.push(Incbin {
+                suffix: "Map",
+                file: path.clone(),
+                compressed: self.lz77,
+            });
+        }
+        if let Some(Output::File(path)) = &self.palette_out {
+            incbins.push(Incbin {
+                suffix: "Palette",
+                file: path.clone(),
+                compressed: self.output.is_none() && self.lz77,
+            });
+        }
+        incbins
+    }
 
+    fn convert(
+        self,
+        input: &[u8],
+        format: Option<ImageFormat>,
+        notes: &mut Vec<String>,
+    ) -> Result<()> {
         // We can't write this using `map` because we want to propagate the
         // result from `load_palette` to the outermost `run` function
         let palette = match self.palette {
@@ -296,20 +941,113 @@ impl ConvertOpts {
             None => None,
         };
 
-        let image = gbagfx::convert_image(&input[..], format, palette)?;
-        image.validate()?;
+        let depth = if self.affine {
+            gbagfx::BitDepth::Eight
+        } else {
+            gbagfx::BitDepth::Four
+        };
+
+        let (image, tile_palettes) = match (self.palettes, self.reduce_colors) {
+            (None, Some(opts)) => {
+                let image = gbagfx::decode_image(input, format)?;
+                let reduced = gbagfx::quantize::reduce_colors(&image, &opts)?;
+                notes.push(format!(
+                    "reduced to {} colors (mean error {:.2}, max error {:.2})",
+                    reduced.image.palette.len(),
+                    reduced.mean_error(),
+                    reduced.max_error()
+                ));
+                reduced.image.validate_with_depth(depth)?;
+                (reduced.image, None)
+            }
+            (None, None) => {
+                let (image, remapped) = gbagfx::convert_image_with_report(
+                    input,
+                    format,
+                    gbagfx::ConvertOptions {
+                        palette,
+                        transparency: self.transparency,
+                        depth,
+                        remap: self.remap,
+                    },
+                )?;
+                for r in remapped {
+                    notes.push(format!(
+                        "remapped #{:02X}{:02X}{:02X} to index {} ({} pixels, first at {},{})",
+                        r.color.r,
+                        r.color.g,
+                        r.color.b,
+                        r.index,
+                        r.count,
+                        r.first.0,
+                        r.first.1
+                    ));
+                }
+                image.validate_with_depth(depth)?;
+                (image, None)
+            }
+            (Some(max_palettes), _) => {
+                let image = gbagfx::decode_image(input, format)?;
+                let image = gbagfx::bg::MultiPaletteImage::from_generic_image(
+                    &image,
+                    max_palettes,
+                    self.transparency,
+                )?;
+                image.validate()?;
+
+                if let Some(path) = self.palette_map {
+                    let map = image
+                        .tile_palettes()
+                        .iter()
+                        .map(|&bank| bank as u8)
+                        .collect::<Vec<_>>();
+                    fs::write(path, map)?;
+                }
+
+                let tile_palettes = image.tile_palettes().to_vec();
+                (image.image, Some(tile_palettes))
+            }
+        };
+
+        let image = match self.tilemap {
+            None => image,
+            Some((path, opts)) if self.affine => {
+                let (tiles, map) = gbagfx::affine::build_affine_map(
+                    &image,
+                    opts.size.map(|(size, _)| size),
+                )?;
+                fs::write(path, maybe_compress(self.lz77, map.encode()))?;
+                tiles
+            }
+            Some((path, opts)) => {
+                let (tiles, tilemap) = gbagfx::bg::build_tilemap(
+                    &image,
+                    tile_palettes.as_deref(),
+                    &opts,
+                )?;
+                fs::write(path, maybe_compress(self.lz77, tilemap.encode()))?;
+                tiles
+            }
+        };
         let image_was_output = matches!(&self.output, Some(_));
 
         if let Some(target) = self.output {
-            let result: Vec<u8> =
-                maybe_compress(self.lz77, gbagfx::encode_tiles(image.tiles()));
+            let result: Vec<u8> = maybe_compress(
+                self.lz77,
+                gbagfx::encode_tiles_with_depth(
+                    image.arranged_tiles(&self.arrangement)?,
+                    depth,
+                ),
+            );
             write_target(target, result, self.force_stdout)?;
         }
 
         if let Some(target) = self.palette_out {
+            let format = palette_out_format(self.palette_format, &target);
+            // Compressing a text file would be silly.
             let result: Vec<u8> = maybe_compress(
-                !image_was_output && self.lz77,
-                image.palette.encode(),
+                !image_was_output && self.lz77 && format == PaletteFormat::Raw,
+                image.palette.write(format),
             );
             write_target(target, result, self.force_stdout)?;
         }
@@ -332,7 +1070,37 @@ fn main() -> Result<()> {
 
     match args.mode {
         Mode::Convert(args) => {
-            args.validate()?.run()?;
+            args.validate().unwrap_or_else(|err| err.exit()).run()?;
+        }
+        Mode::Build(args) => {
+            args.run()?;
+        }
+        Mode::Unconvert(args) => {
+            args.run()?;
+        }
+        Mode::Frames(args) => {
+            args.run()?;
+        }
+        Mode::Obj(args) => {
+            args.run()?;
+        }
+        Mode::Portrait(args) => {
+            args.run()?;
+        }
+        Mode::Banim(args) => {
+            args.run()?;
+        }
+        Mode::Chipset(args) => {
+            args.run()?;
+        }
+        Mode::Icons(args) => {
+            args.run()?;
+        }
+        Mode::Font(args) => {
+            args.run()?;
+        }
+        Mode::PaletteAnim(args) => {
+            args.run()?;
         }
     }
 
@@ -0,0 +1,106 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::{ArgAction, Parser, ValueEnum};
+use image::ImageFormat;
+
+use crate::{gbagfx, load_palette, maybe_compress};
+
+use gbagfx::obj::{Mapping, Sprite, SpriteOptions};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum MappingArg {
+    #[default]
+    #[value(name = "1d")]
+    OneD,
+    #[value(name = "2d")]
+    TwoD,
+}
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct ObjArgs {
+    input: PathBuf,
+    /// Output tile data
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Output OAM data (a halfword count, then three halfwords per piece)
+    #[arg(long)]
+    oam: PathBuf,
+    #[arg(short = 'p', long)]
+    palette_out: Option<PathBuf>,
+    /// Use the specified palette instead of the input image's.
+    #[arg(long)]
+    palette_in: Option<String>,
+    /// VRAM mapping layout to arrange tiles for.
+    #[arg(long, value_enum, default_value_t)]
+    mapping: MappingArg,
+    /// Position (in image pixels) that piece offsets are relative to, as X,Y.
+    #[arg(long, value_parser = parse_origin, default_value = "0,0")]
+    origin: (i32, i32),
+    /// Palette bank to use in the OAM data.
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..16))]
+    palette_bank: u8,
+    /// Compress tile data
+    #[arg(long, action=ArgAction::SetTrue)]
+    lz77: bool,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl ObjArgs {
+    pub fn run(self) -> Result<()> {
+        let format = ImageFormat::from_path(&self.input).ok();
+        let input = fs::read(&self.input)?;
+
+        let palette = match self.palette_in {
+            Some(s) => Some(load_palette(s)?),
+            None => None,
+        };
+
+        // Sprites always treat index 0 as transparent, so we'd better put
+        // transparent pixels there.
+        let image = gbagfx::convert_image_with_options(
+            &input[..],
+            format,
+            gbagfx::ConvertOptions {
+                palette,
+                transparency: Some(Default::default()),
+                ..Default::default()
+            },
+        )?;
+
+        let sprite = Sprite::from_image(
+            &image,
+            &SpriteOptions {
+                mapping: match self.mapping {
+                    MappingArg::OneD => Mapping::OneD,
+                    MappingArg::TwoD => Mapping::TwoD,
+                },
+                origin: self.origin,
+                palette: self.palette_bank as usize,
+            },
+        )?;
+
+        fs::write(
+            self.output,
+            maybe_compress(self.lz77, sprite.encode_tiles()),
+        )?;
+        fs::write(self.oam, sprite.encode_oam())?;
+
+        if let Some(path) = self.palette_out {
+            fs::write(path, image.palette.encode())?;
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_origin(s: &str) -> Result<(i32, i32), String> {
+    s.split_once(',')
+        .and_then(|(x, y)| {
+            Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+        })
+        .ok_or_else(|| "expected X,Y".to_string())
+}
@@ -0,0 +1,133 @@
+use std::{fmt::Write as _, fs, ops::Range, path::PathBuf};
+
+use anyhow::{bail, Result};
+use clap::{value_parser, ArgAction, Parser};
+
+use crate::{
+    gbagfx, is_identifier, label_from_path, load_palette, parse_rgb, Incbin,
+    Installer,
+};
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct PaletteAnimArgs {
+    /// Palette to animate, in any form accepted by `convert --palette-in`.
+    palette: String,
+    /// Output palette frames, one after another.
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Fade towards this color: `black`, `white`, or RRGGBB.
+    #[arg(
+        long,
+        value_parser = parse_fade_target,
+        required_unless_present = "cycle",
+        conflicts_with = "cycle"
+    )]
+    fade_to: Option<gbagfx::Color>,
+    /// How far to fade, in percent. Less than 100 tints the palette instead
+    /// of fading it out completely.
+    #[arg(
+        long,
+        requires = "fade_to",
+        default_value_t = 100,
+        value_parser = value_parser!(u32).range(0..=100)
+    )]
+    amount: u32,
+    /// Rotate the colors from index FIRST to LAST (inclusive), given as
+    /// `FIRST-LAST`, one place each frame.
+    #[arg(long, value_parser = parse_index_range)]
+    cycle: Option<Range<usize>>,
+    /// Rotate towards lower indices instead.
+    #[arg(long, requires = "cycle", action=ArgAction::SetTrue)]
+    reverse: bool,
+    /// Number of frames, counting the unchanged first one. Defaults to 16
+    /// for fades, or one full turn for cycles.
+    #[arg(long, value_parser = value_parser!(u64).range(1..))]
+    frames: Option<u64>,
+    /// Write an Event Assembler installer with the frames and a table of
+    /// pointers to each one.
+    #[arg(long)]
+    table: Option<PathBuf>,
+    /// Label for the frames in the installer; the table gets
+    /// `<LABEL>Frames`. Defaults to the output's file name.
+    #[arg(long, requires = "table")]
+    label: Option<String>,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl PaletteAnimArgs {
+    pub fn run(self) -> Result<()> {
+        let palette = load_palette(&self.palette)?;
+
+        let frames = match (self.fade_to, self.cycle) {
+            (Some(target), _) => palette.fade(
+                target,
+                self.frames.unwrap_or(16) as usize,
+                self.amount,
+            ),
+            (None, Some(range)) => {
+                let frames = self.frames.map_or(range.len(), |n| n as usize);
+                palette.cycle(range, frames, self.reverse)?
+            }
+            // Clap requires one or the other.
+            (None, None) => unreachable!(),
+        };
+
+        let data = frames.iter().flat_map(|p| p.encode()).collect::<Vec<_>>();
+        fs::write(&self.output, data)?;
+
+        if let Some(path) = self.table {
+            let label = match self.label {
+                Some(label) if is_identifier(&label) => label,
+                Some(label) => bail!("`{}` isn't a valid label", label),
+                None => label_from_path(&self.output),
+            };
+            let installer = Installer {
+                path,
+                label,
+                repoint: None,
+                repoint_palette: None,
+            };
+            let (_, data) = installer.render(&[Incbin {
+                suffix: "",
+                file: self.output.clone(),
+                compressed: false,
+            }])?;
+
+            let mut table = String::new();
+            writeln!(table, "// Palette frames for {}.", self.palette)?;
+            writeln!(table)?;
+            table.push_str(&data);
+            writeln!(table, "ALIGN 4")?;
+            writeln!(table, "{}Frames:", installer.label)?;
+            let size = palette.len() * 2;
+            for i in 0..frames.len() {
+                writeln!(table, "POIN {}+{:#X}", installer.label, i * size)?;
+            }
+            fs::write(&installer.path, table)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_fade_target(s: &str) -> Result<gbagfx::Color, String> {
+    match s.to_lowercase().as_str() {
+        "black" => Ok(gbagfx::Color::rgb(0, 0, 0)),
+        "white" => Ok(gbagfx::Color::rgb(0xFF, 0xFF, 0xFF)),
+        _ => parse_rgb(s),
+    }
+}
+
+fn parse_index_range(s: &str) -> Result<Range<usize>, String> {
+    let err = || format!("expected FIRST-LAST, got `{}`", s);
+    let (first, last) = s.split_once('-').ok_or_else(err)?;
+    let first = first.trim().parse::<usize>().map_err(|_| err())?;
+    let last = last.trim().parse::<usize>().map_err(|_| err())?;
+    if last < first {
+        return Err(err());
+    }
+    Ok(first..last + 1)
+}
@@ -0,0 +1,157 @@
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use image::ImageFormat;
+
+use crate::{gbagfx, label_from_name, load_palette, maybe_compress};
+
+use gbagfx::portrait::Portrait;
+
+// The game expects this before the (uncompressed) main portrait tiles.
+const MAIN_HEADER: [u8; 4] = [0x00, 0x04, 0x10, 0x00];
+
+// Size of one portrait table entry.
+const ENTRY_SIZE: usize = 0x1C;
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct PortraitArgs {
+    /// A 128x112 portrait sheet.
+    input: PathBuf,
+    /// Directory to write the portrait data and installer to.
+    #[arg(short, long, default_value = ".")]
+    out_dir: PathBuf,
+    /// Prefix for output files and installer labels. Defaults to the input
+    /// file's name.
+    #[arg(long)]
+    name: Option<String>,
+    /// Index of the portrait table entry to write.
+    #[arg(long)]
+    index: usize,
+    /// Position of the mouth in the main portrait, in tiles, as X,Y.
+    /// Defaults to wherever the first mouth frame appears in it.
+    #[arg(long, value_parser = parse_tile_position)]
+    mouth: Option<(usize, usize)>,
+    /// Position of the eyes in the main portrait, in tiles, as X,Y.
+    #[arg(long, value_parser = parse_tile_position)]
+    eyes: (usize, usize),
+    /// Use the specified palette instead of the input image's.
+    #[arg(long)]
+    palette_in: Option<String>,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl PortraitArgs {
+    pub fn run(self) -> Result<()> {
+        let format = ImageFormat::from_path(&self.input).ok();
+        let input = fs::read(&self.input)?;
+
+        let palette = match self.palette_in {
+            Some(s) => Some(load_palette(s)?),
+            None => None,
+        };
+
+        let sheet = gbagfx::convert_image(&input[..], format, palette)?;
+        let portrait = Portrait::from_sheet(&sheet)?;
+
+        let (mouth_x, mouth_y) =
+            self.mouth.or(portrait.mouth_position).ok_or_else(|| {
+                anyhow!("couldn't find the mouth in the portrait; use --mouth")
+            })?;
+        let (eyes_x, eyes_y) = self.eyes;
+
+        let name = match self.name {
+            Some(name) => name,
+            None => self
+                .input
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .ok_or_else(|| anyhow!("can't name output; use --name"))?,
+        };
+        let label = label_from_name(&name);
+
+        // Portrait palettes are always a full 16 colors.
+        let mut palette = sheet.palette.encode();
+        palette.resize(32, 0);
+
+        let mut main = MAIN_HEADER.to_vec();
+        main.extend(gbagfx::encode_tiles(portrait.main.tiles()));
+
+        let files = [
+            ("Mug", "mug", main),
+            (
+                "Minimug",
+                "minimug",
+                maybe_compress(
+                    true,
+                    gbagfx::encode_tiles(portrait.minimug.tiles()),
+                ),
+            ),
+            ("Palette", "palette", palette),
+            (
+                "Mouth",
+                "mouth",
+                gbagfx::encode_tiles(portrait.mouths.tiles()),
+            ),
+        ];
+
+        let mut installer = String::new();
+        writeln!(installer, "// Portrait installer for {}.", name)?;
+        writeln!(installer)?;
+        writeln!(installer, "#ifndef PortraitTable")?;
+        writeln!(
+            installer,
+            "  ERROR \"PortraitTable must be defined as the portrait table's address\""
+        )?;
+        writeln!(installer, "#endif")?;
+        writeln!(installer)?;
+        writeln!(installer, "PUSH")?;
+        writeln!(
+            installer,
+            "ORG PortraitTable + {:#X}",
+            self.index * ENTRY_SIZE
+        )?;
+        writeln!(
+            installer,
+            "POIN {0}Mug {0}Minimug {0}Palette {0}Mouth 0",
+            label
+        )?;
+        writeln!(
+            installer,
+            "BYTE {} {} {} {}",
+            mouth_x, mouth_y, eyes_x, eyes_y
+        )?;
+        // No eye frames are written, so don't blink.
+        writeln!(installer, "WORD 0")?;
+        writeln!(installer, "POP")?;
+        writeln!(installer)?;
+        writeln!(installer, "ALIGN 4")?;
+
+        for (suffix, file_suffix, data) in files {
+            let file = format!("{}_{}.dmp", name, file_suffix);
+            fs::write(self.out_dir.join(&file), data)?;
+
+            writeln!(installer, "{}{}:", label, suffix)?;
+            writeln!(installer, "#incbin \"{}\"", file)?;
+            writeln!(installer, "ALIGN 4")?;
+        }
+
+        fs::write(
+            self.out_dir.join(format!("{}_installer.event", name)),
+            installer,
+        )?;
+
+        Ok(())
+    }
+}
+
+fn parse_tile_position(s: &str) -> Result<(usize, usize), String> {
+    s.split_once(',')
+        .and_then(|(x, y)| {
+            Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+        })
+        .ok_or_else(|| "expected X,Y (in tiles)".to_string())
+}
@@ -0,0 +1,246 @@
+use super::*;
+
+fn installer(path: &str) -> Installer {
+    Installer {
+        path: PathBuf::from(path),
+        label: "Gfx".to_string(),
+        repoint: None,
+        repoint_palette: None,
+    }
+}
+
+#[test]
+fn labels_are_valid_identifiers() {
+    assert_eq!(
+        label_from_path(Path::new("gfx/title-screen.png")),
+        "title_screen"
+    );
+    assert_eq!(label_from_path(Path::new("1st map.png")), "_1st_map");
+    assert_eq!(label_from_name("Élan"), "_lan");
+}
+
+#[test]
+fn incbin_paths_are_relative_to_the_installer() {
+    let installer = installer("/rom/events/gfx.event");
+    let path = |file: &str| installer.relative_path(Path::new(file)).unwrap();
+    assert_eq!(path("/rom/events/gfx.dmp"), "gfx.dmp");
+    assert_eq!(path("/rom/events/out/gfx.dmp"), "out/gfx.dmp");
+    assert_eq!(path("/rom/gfx/title.dmp"), "../gfx/title.dmp");
+    assert_eq!(path("/other/title.dmp"), "../../other/title.dmp");
+}
+
+#[test]
+fn installer_repoints_and_sizes_its_outputs() {
+    let dir = std::env::temp_dir().join("tilemage-installer-test");
+    fs::create_dir_all(&dir).unwrap();
+    let palette = dir.join("gfx.pal.dmp");
+    fs::write(&palette, [0; 32]).unwrap();
+
+    let installer = Installer {
+        repoint_palette: Some("0x8B0000".to_string()),
+        ..installer(dir.join("gfx.event").to_str().unwrap())
+    };
+    let (table, data) = installer
+        .render(&[
+            Incbin {
+                suffix: "",
+                file: dir.join("gfx.dmp"),
+                compressed: true,
+            },
+            Incbin {
+                suffix: "Palette",
+                file: palette,
+                compressed: false,
+            },
+        ])
+        .unwrap();
+
+    assert_eq!(table, "ORG 0x8B0000\nPOIN GfxPalette\n");
+    assert_eq!(
+        data,
+        "ALIGN 4\nGfx:\n#incbin \"gfx.dmp\"\n\
+         #define GfxPaletteSize 0x20\n\
+         ALIGN 4\nGfxPalette:\n#incbin \"gfx.pal.dmp\"\n"
+    );
+}
+
+#[test]
+fn installer_needs_a_raw_palette() {
+    let args = |palette_out: &str| {
+        ConvertArgs::try_parse_from([
+            "convert",
+            "in.png",
+            "-o",
+            "out.dmp",
+            "-p",
+            palette_out,
+            "--installer",
+            "out.event",
+        ])
+        .unwrap()
+        .validate()
+    };
+    assert!(args("out.pal.dmp").is_ok());
+    assert!(args("out.gpl").is_err());
+}
+
+#[test]
+fn manifest_entries_become_convert_arguments() {
+    use toml::Table;
+
+    let defaults = "lz77 = true\nno_flips = true".parse::<Table>().unwrap();
+    // A palette written out in hex isn't a path.
+    let hex = format!("0000FF7F{}", "0".repeat(56));
+    let image = format!(
+        "input = \"title.png\"\noutput = \"title.dmp\"\nno_flips = false\n\
+         palette-in = \"{}\"\nrepoint = \"0x8B0000\"",
+        hex
+    )
+    .parse::<Table>()
+    .unwrap();
+
+    let entry = build::entry(
+        0,
+        &defaults,
+        &image,
+        Path::new("gfx"),
+        Some(Path::new("all.event")),
+    )
+    .unwrap();
+    assert_eq!(entry.name, "title.png");
+    assert_eq!(
+        entry.args,
+        [
+            "tilemage build",
+            "--lz77",
+            "--output=gfx/title.dmp",
+            &format!("--palette-in={}", hex),
+            "--repoint=0x8B0000",
+            "--installer=all.event",
+            "--",
+            "gfx/title.png",
+        ]
+    );
+
+    // Installer options need an installer, and some options never apply.
+    let dir = Path::new("");
+    assert!(build::entry(0, &defaults, &image, dir, None).is_err());
+    let stdout = "input = \"a.png\"\nto_stdout = true"
+        .parse::<Table>()
+        .unwrap();
+    assert!(build::entry(0, &Table::new(), &stdout, dir, None).is_err());
+    let no_input = "output = \"a.dmp\"".parse::<Table>().unwrap();
+    assert!(build::entry(0, &Table::new(), &no_input, dir, None).is_err());
+}
+
+#[test]
+fn manifest_images_cant_share_outputs() {
+    use toml::Table;
+
+    // Each image writes its name's .dmp, and maybe a palette.
+    let entry = |name: &str, palette_out: &str| {
+        let image = format!(
+            "input = \"{0}.png\"\noutput = \"{0}.dmp\"\n{1}",
+            name, palette_out
+        )
+        .parse::<Table>()
+        .unwrap();
+        let entry =
+            build::entry(0, &Table::new(), &image, Path::new("gfx"), None)
+                .unwrap();
+        (entry.name.clone(), build::validate(&entry).unwrap())
+    };
+    let shared = "palette-out = \"pal.dmp\"";
+
+    let jobs = [entry("a", shared), entry("c", "")];
+    assert!(build::check_outputs(&jobs).is_ok());
+    let err = build::check_outputs(&[entry("a", shared), entry("b", shared)])
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("a.png and b.png both write"), "{}", err);
+}
+
+#[test]
+fn up_to_date_compares_input_and_output_times() {
+    use std::time::{Duration, SystemTime};
+
+    let dir = std::env::temp_dir().join("tilemage-up-to-date-test");
+    fs::create_dir_all(&dir).unwrap();
+    let (input, output) = (dir.join("in.png"), dir.join("out.dmp"));
+    let _ = fs::remove_file(&output);
+    fs::write(&input, []).unwrap();
+
+    let opts = ConvertArgs::try_parse_from([
+        std::ffi::OsString::from("convert"),
+        input.clone().into(),
+        "-o".into(),
+        output.clone().into(),
+    ])
+    .unwrap()
+    .validate()
+    .unwrap();
+    let then = SystemTime::now() - Duration::from_secs(60);
+    let later = SystemTime::now() + Duration::from_secs(60);
+    let set_time = |path: &Path, time: SystemTime| {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap()
+    };
+
+    // No output yet.
+    assert!(!opts.up_to_date(None));
+
+    fs::write(&output, []).unwrap();
+    set_time(&input, then);
+    assert!(opts.up_to_date(Some(then)));
+    // A newer manifest might have changed the options.
+    assert!(!opts.up_to_date(Some(later)));
+    // And a newer input needs converting again.
+    set_time(&input, later);
+    assert!(!opts.up_to_date(Some(then)));
+}
+
+#[test]
+fn banim_frames_share_a_palette() {
+    use image::{Rgba, RgbaImage};
+
+    let dir = std::env::temp_dir().join("tilemage-banim-test");
+    fs::create_dir_all(&dir).unwrap();
+
+    // The second frame brings in a color the first doesn't have.
+    let red = Rgba([0xF8, 0, 0, 0xFF]);
+    let green = Rgba([0, 0xF8, 0, 0xFF]);
+    let mut frame = RgbaImage::new(64, 32);
+    frame.put_pixel(0, 0, red);
+    frame.save(dir.join("a.png")).unwrap();
+    frame.put_pixel(8, 0, green);
+    frame.save(dir.join("b.png")).unwrap();
+
+    let mut script = String::new();
+    for mode in 1..=12 {
+        script.push_str(&format!("/// - Mode {}\n", mode));
+        if mode == 1 {
+            script.push_str("3 p- a.png\n2 p- b.png\n");
+        }
+        script.push_str("~~~\n\n");
+    }
+    let script_path = dir.join("anim.txt");
+    fs::write(&script_path, script).unwrap();
+
+    banim::BanimArgs::try_parse_from([
+        std::ffi::OsString::from("banim"),
+        script_path.into(),
+        "--out-dir".into(),
+        dir.clone().into(),
+        "--index".into(),
+        "1".into(),
+    ])
+    .unwrap()
+    .run()
+    .unwrap();
+    assert!(dir.join("anim.bin").exists());
+    assert!(dir.join("anim_installer.event").exists());
+}
@@ -0,0 +1,88 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::Result;
+use clap::{
+    builder::{PossibleValuesParser, TypedValueParser},
+    ArgAction, Parser,
+};
+
+use crate::{gbagfx, load_palette, maybe_decompress, PreviewArg};
+
+#[derive(Parser, Debug)]
+#[command(disable_help_flag = true)]
+pub struct UnconvertArgs {
+    /// Raw tile data.
+    input: PathBuf,
+    /// Output png (derived from input if absent)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Palette to render with, in any form accepted by `convert
+    /// --palette-in`. Defaults to a grayscale ramp.
+    #[arg(short = 'p', long)]
+    palette: Option<String>,
+    /// Width of the output image, in tiles.
+    #[arg(short, long, default_value_t = 16)]
+    width: usize,
+    /// Bits per pixel of the tile data.
+    #[arg(
+        long,
+        default_value = "4",
+        value_parser = PossibleValuesParser::new(["1", "2", "4", "8"])
+            .map(|s| s.parse::<u8>().unwrap()),
+    )]
+    bpp: u8,
+    /// Input is lz77-compressed
+    #[arg(long, action=ArgAction::SetTrue)]
+    lz77: bool,
+    /// How to turn the GBA's 5-bit colors into the PNG's 8-bit ones: `raw`
+    /// shifts them up as stored, `expanded` fills the full 0-255 range, and
+    /// `lcd` also corrects for the GBA screen's color response.
+    #[arg(long, value_enum, default_value_t)]
+    preview: PreviewArg,
+    /// Print help information
+    #[arg(long, global=true, action=clap::ArgAction::HelpLong)]
+    help: Option<bool>,
+}
+
+impl UnconvertArgs {
+    pub fn run(self) -> Result<()> {
+        let depth = match self.bpp {
+            1 => gbagfx::BitDepth::One,
+            2 => gbagfx::BitDepth::Two,
+            4 => gbagfx::BitDepth::Four,
+            _ => gbagfx::BitDepth::Eight,
+        };
+
+        let palette = match self.palette {
+            Some(s) => load_palette(s)?,
+            None => grayscale(depth),
+        };
+
+        let output = match self.output {
+            Some(output) => output,
+            None => self.input.with_extension("png"),
+        };
+
+        let data = maybe_decompress(self.lz77, fs::read(&self.input)?)?;
+        let image = gbagfx::GBAImage::from_tiles_with_depth(
+            &data[..],
+            palette,
+            self.width,
+            depth,
+        )?;
+
+        fs::write(output, image.encode_png_with_preview(self.preview.into())?)?;
+
+        Ok(())
+    }
+}
+
+fn grayscale(depth: gbagfx::BitDepth) -> gbagfx::Palette {
+    let levels = 1usize << depth.bits();
+    (0..levels)
+        .map(|i| {
+            let v = (i * 255 / (levels - 1)) as u8;
+            gbagfx::Color::rgb(v, v, v)
+        })
+        .collect()
+}
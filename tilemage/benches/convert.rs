@@ -0,0 +1,107 @@
+// Batch conversion benchmarks, roughly the work of re-ripping and rebuilding
+// every graphic in a ROM. Run with `cargo bench -p tilemage`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use image::{Rgb, RgbImage};
+use tilemage::{encode_tiles, ConvertOptions, GBAImage, Palette};
+
+// A ROM's worth of 4bpp graphics, give or take.
+const TILES: usize = 64 * 1024;
+const SHEET_TILES_WIDE: usize = 32;
+const RUNS: u32 = 5;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    f();
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        f();
+        best = best.min(start.elapsed());
+    }
+    println!("{:<28} {:>10.2?}", name, best);
+}
+
+fn main() {
+    // Pseudo-random tile data, so nothing dedupes or compresses too well.
+    let mut seed = 0x2545_f491_u32;
+    let rom = (0..TILES * 32)
+        .map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as u8
+        })
+        .collect::<Vec<_>>();
+    let palette = (0..16)
+        .map(|i| tilemage::Color::rgb(i * 8, 0xF8 - i * 8, i * 4))
+        .collect::<Palette>();
+
+    let sheets = rom
+        .chunks(SHEET_TILES_WIDE * 32 * 32)
+        .map(|chunk| {
+            GBAImage::from_tiles(chunk, palette.clone(), SHEET_TILES_WIDE)
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    let rgb = sheets
+        .iter()
+        .map(|sheet| {
+            RgbImage::from_fn(
+                sheet.width as u32,
+                sheet.height as u32,
+                |x, y| {
+                    let c = sheet.color_at(x as usize, y as usize).unwrap();
+                    Rgb([c.r, c.g, c.b])
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    println!(
+        "{} tiles in {} sheets, best of {}",
+        TILES,
+        sheets.len(),
+        RUNS
+    );
+
+    bench("rip (from_tiles)", || {
+        for chunk in rom.chunks(SHEET_TILES_WIDE * 32 * 32) {
+            black_box(
+                GBAImage::from_tiles(chunk, palette.clone(), SHEET_TILES_WIDE)
+                    .unwrap(),
+            );
+        }
+    });
+
+    bench("encode_tiles", || {
+        for sheet in &sheets {
+            black_box(encode_tiles(sheet.tiles()));
+        }
+    });
+
+    bench("pixel iteration", || {
+        for sheet in &sheets {
+            black_box(sheet.tiles().flat_map(|t| t.pixels()).sum::<usize>());
+        }
+    });
+
+    bench("convert (known palette)", || {
+        let opts = ConvertOptions {
+            palette: Some(palette.clone()),
+            ..Default::default()
+        };
+        for img in &rgb {
+            black_box(GBAImage::convert(img, &opts).unwrap());
+        }
+    });
+
+    bench("convert + encode", || {
+        for img in &rgb {
+            let image = GBAImage::with_inferred_palette(img).unwrap();
+            black_box(encode_tiles(image.tiles()));
+        }
+    });
+}
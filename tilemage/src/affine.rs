@@ -0,0 +1,128 @@
+// Affine (rotation/scaling) background support.
+//
+// Affine backgrounds are much more limited than text ones: tiles are always
+// 8bpp, sharing the one 256-color palette, and each tilemap entry is a single
+// byte holding the tile number. There are no flips or palette banks, so a
+// map can only reference 256 distinct tiles. Maps are always square, and
+// only a few sizes are allowed.
+
+use std::collections::HashMap;
+
+use crate::{BitDepth, Error, GBAImage};
+
+pub const MAX_TILES: usize = 256;
+
+// Legal map sizes, in tiles (128x128 up to 1024x1024 px).
+pub const SIZES: [usize; 4] = [16, 32, 64, 128];
+
+pub struct AffineMap {
+    // In tiles. Always one of `SIZES`.
+    pub size: usize,
+    // INVARIANT: entries.len() = size * size, row-major.
+    entries: Vec<u8>,
+}
+
+impl AffineMap {
+    pub fn entry_at(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.size || y >= self.size {
+            return None;
+        }
+
+        self.entries.get(y * self.size + x).copied()
+    }
+
+    pub fn entries(&self) -> &[u8] {
+        &self.entries
+    }
+
+    // Unlike text backgrounds, affine maps aren't split into screenblocks;
+    // the bytes are simply row-major.
+    pub fn encode(&self) -> Vec<u8> {
+        self.entries.clone()
+    }
+}
+
+// Picks the smallest legal size that fits [width] x [height] tiles.
+pub fn fit_size(width: usize, height: usize) -> Option<usize> {
+    SIZES
+        .into_iter()
+        .find(|&size| size >= width && size >= height)
+}
+
+// Splits [image] into unique tiles and an affine map referencing them. The
+// returned sheet is a single column of 8x8 tiles, meant to be encoded with
+// `encode_tiles_with_depth(.., BitDepth::Eight)`.
+//
+// The map is [size] tiles square if given, and otherwise the smallest legal
+// size that fits the image. Any space the image doesn't cover is filled with
+// a blank tile.
+pub fn build_affine_map(
+    image: &GBAImage,
+    size: Option<usize>,
+) -> Result<(GBAImage, AffineMap), Error> {
+    if !image.width.is_multiple_of(8) || !image.height.is_multiple_of(8) {
+        return Err(Error::BadDimensions(image.width, image.height));
+    }
+    image.validate_with_depth(BitDepth::Eight)?;
+
+    let tiles_wide = image.width / 8;
+    let tiles_high = image.height / 8;
+    let size = match size {
+        Some(size) if SIZES.contains(&size) => size,
+        Some(_) => return Err(Error::BadMapSize),
+        None => fit_size(tiles_wide, tiles_high).ok_or(Error::BadMapSize)?,
+    };
+
+    if size < tiles_wide || size < tiles_high {
+        return Err(Error::BadMapSize);
+    }
+
+    let mut seen: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut data: Vec<u8> = Vec::new();
+    let mut tiles = Vec::with_capacity(tiles_wide * tiles_high);
+
+    for tile in image.tiles() {
+        let pixels = tile.indices().collect::<Vec<_>>();
+        let next = seen.len();
+        let idx = *seen.entry(pixels.clone()).or_insert_with(|| {
+            data.extend(pixels);
+            next
+        });
+        tiles.push(idx);
+    }
+
+    let mut entries = vec![0; size * size];
+    if (size, size) != (tiles_wide, tiles_high) {
+        let blank = match seen.get(&vec![0; 64]) {
+            Some(&tile) => tile,
+            None => {
+                data.extend(std::iter::repeat_n(0, 64));
+                seen.len()
+            }
+        };
+        entries.fill(blank);
+    }
+    for (i, tile) in tiles.into_iter().enumerate() {
+        entries[(i / tiles_wide) * size + i % tiles_wide] = tile;
+    }
+
+    let tile_count = data.len() / 64;
+    if tile_count > MAX_TILES {
+        return Err(Error::TooManyTiles);
+    }
+
+    let sheet = GBAImage {
+        palette: image.palette.clone(),
+        width: 8,
+        height: 8 * tile_count,
+        data,
+    };
+
+    Ok((
+        sheet,
+        AffineMap {
+            size,
+            entries: entries.into_iter().map(|tile| tile as u8).collect(),
+        },
+    ))
+}
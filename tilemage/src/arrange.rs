@@ -0,0 +1,113 @@
+// Tile arrangements.
+//
+// `GBAImage::tiles` walks the image row by row, which is what most hardware
+// wants. Plenty of data formats lay their tiles out differently, though: 16x16
+// metatiles stored as four consecutive 8x8 tiles, column-major strips, or a
+// handful of regions cut out of a bigger sheet (like the parts of a portrait).
+// An `Arrangement` describes such an order, and `GBAImage::arranged_tiles`
+// walks the image in it, ready to hand to `encode_tiles`.
+
+use crate::Error;
+
+// A rectangle of tiles. Everything here is in tiles, not pixels.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TileRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl TileRect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn row_major(self) -> impl Iterator<Item = (usize, usize)> {
+        (self.y..self.y + self.height).flat_map(move |y| {
+            (self.x..self.x + self.width).map(move |x| (x, y))
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum Arrangement {
+    // Left to right, then top to bottom. Same as `GBAImage::tiles`.
+    #[default]
+    RowMajor,
+    // Top to bottom, then left to right.
+    ColumnMajor,
+    // The image is cut into blocks of this many tiles, which are visited
+    // row-major; the tiles of each block are also visited row-major. 2x2
+    // blocks give the usual 16x16 metatile order (TL, TR, BL, BR).
+    Blocks {
+        width: usize,
+        height: usize,
+    },
+    // Each rectangle in turn, row-major within each one. Tiles outside of
+    // every rectangle are skipped.
+    Regions(Vec<TileRect>),
+}
+
+impl Arrangement {
+    pub fn metatiles() -> Self {
+        Self::Blocks {
+            width: 2,
+            height: 2,
+        }
+    }
+
+    // Tile coordinates, in the order they should be visited, for an image
+    // [tiles_wide] x [tiles_high] tiles in size.
+    pub fn order(
+        &self,
+        tiles_wide: usize,
+        tiles_high: usize,
+    ) -> Result<Vec<(usize, usize)>, Error> {
+        let whole = TileRect::new(0, 0, tiles_wide, tiles_high);
+
+        match self {
+            Self::RowMajor => Ok(whole.row_major().collect()),
+            Self::ColumnMajor => Ok((0..tiles_wide)
+                .flat_map(|x| (0..tiles_high).map(move |y| (x, y)))
+                .collect()),
+            &Self::Blocks { width, height } => {
+                if width == 0
+                    || height == 0
+                    || !tiles_wide.is_multiple_of(width)
+                    || !tiles_high.is_multiple_of(height)
+                {
+                    return Err(Error::BadArrangement);
+                }
+
+                let blocks = TileRect::new(
+                    0,
+                    0,
+                    tiles_wide / width,
+                    tiles_high / height,
+                );
+                Ok(blocks
+                    .row_major()
+                    .flat_map(|(bx, by)| {
+                        TileRect::new(bx * width, by * height, width, height)
+                            .row_major()
+                    })
+                    .collect())
+            }
+            Self::Regions(rects) => {
+                if rects.iter().any(|r| {
+                    r.x + r.width > tiles_wide || r.y + r.height > tiles_high
+                }) {
+                    return Err(Error::BadArrangement);
+                }
+
+                Ok(rects.iter().flat_map(|r| r.row_major()).collect())
+            }
+        }
+    }
+}
@@ -0,0 +1,356 @@
+// Fire Emblem battle animations.
+//
+// Animations are written as a text script alongside a folder of frame images,
+// in the format FEditor popularized:
+//
+//   /// - Mode 1
+//   C01
+//   S0034
+//   3 p- sword_000.png
+//   ~~~
+//
+// Each mode (there are always 12) lists commands (`C##`), sound effects
+// (`S####`) and frames (`delay p- image`), and ends with `~~~`.
+//
+// In the game, this becomes:
+//   * graphics sheets, each 32x8 tiles, holding the sprites of one or more
+//     frames (LZ77-compressed);
+//   * the script itself, one 32-bit word per command, with frames taking
+//     three: the delay and frame number, a pointer to the frame's sheet, and
+//     the offset of its OAM data (LZ77-compressed);
+//   * OAM data for each frame, once as drawn (for the unit on the right) and
+//     once mirrored (for the unit on the left) (LZ77-compressed);
+//   * four 16-color palettes, one per allegiance (LZ77-compressed);
+//   * a table of where each mode starts in the script.
+//
+// The script holds pointers to the sheets, so it can't be compressed ahead of
+// time without knowing where the sheets will go. We leave those pointers
+// relative and store the script as literal LZ77 blocks, which keeps each
+// pointer's bytes in one spot for the installer to fill in.
+
+use std::collections::HashMap;
+
+use gbalz77::CompressionStrategy;
+
+use crate::obj::{Mapping, Sprite, SpriteOptions};
+use crate::{Error, GBAImage, Palette};
+
+pub const MODE_COUNT: usize = 12;
+
+// Sheets are always 32 tiles wide (like 2D-mapped VRAM) and 8 tiles high.
+const SHEET_TILES_HIGH: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    // `C##`
+    Command(u8),
+    // `S####`
+    Sound(u16),
+    // `delay p- image`
+    Frame { delay: u16, image: String },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mode {
+    // 1 to 12.
+    pub number: usize,
+    pub commands: Vec<Command>,
+}
+
+// Returns every mode in the script, in order. All 12 must be present.
+pub fn parse_script(text: &str) -> Result<Vec<Mode>, Error> {
+    let mut modes: Vec<Mode> = Vec::new();
+    let mut current: Option<Mode> = None;
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let bad = |reason| Error::BadScript(i + 1, reason);
+
+        if let Some(number) = line.strip_prefix("/// - Mode ") {
+            let number = number
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=MODE_COUNT).contains(n))
+                .ok_or(bad("mode number must be from 1 to 12"))?;
+            modes.extend(current.take());
+            current = Some(Mode {
+                number,
+                commands: Vec::new(),
+            });
+            continue;
+        }
+
+        if line == "~~~" {
+            modes.extend(current.take());
+            continue;
+        }
+
+        if line.is_empty() || line.starts_with("//") || line.starts_with('#') {
+            continue;
+        }
+
+        let mode = current.as_mut().ok_or(bad("command outside of a mode"))?;
+
+        let command = if let Some(hex) = line.strip_prefix('C') {
+            u8::from_str_radix(hex, 16)
+                .map(Command::Command)
+                .map_err(|_| bad("expected a command like C01"))?
+        } else if let Some(hex) = line.strip_prefix('S') {
+            u16::from_str_radix(hex, 16)
+                .map(Command::Sound)
+                .map_err(|_| bad("expected a sound like S0034"))?
+        } else {
+            let mut fields = line.splitn(3, char::is_whitespace);
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(delay), Some("p-"), Some(image)) => Command::Frame {
+                    delay: delay
+                        .parse()
+                        .map_err(|_| bad("frame delay must be a number"))?,
+                    image: image.trim().to_string(),
+                },
+                _ => return Err(bad("expected a frame like `3 p- frame.png`")),
+            }
+        };
+
+        mode.commands.push(command);
+    }
+    modes.extend(current);
+
+    modes.sort_by_key(|mode| mode.number);
+    for number in 1..=MODE_COUNT {
+        if modes.iter().filter(|mode| mode.number == number).count() != 1 {
+            return Err(Error::BadMode(number));
+        }
+    }
+
+    Ok(modes)
+}
+
+// Every frame image named in [modes], in order of first appearance.
+pub fn frame_images(modes: &[Mode]) -> Vec<&str> {
+    let mut images: Vec<&str> = Vec::new();
+    for command in modes.iter().flat_map(|mode| mode.commands.iter()) {
+        if let Command::Frame { image, .. } = command {
+            if !images.contains(&image.as_str()) {
+                images.push(image);
+            }
+        }
+    }
+    images
+}
+
+#[derive(Clone, Debug)]
+pub struct BanimOptions {
+    // Where the unit stands, in frame image pixels. OAM offsets are relative
+    // to this.
+    pub origin: (i32, i32),
+}
+
+impl Default for BanimOptions {
+    fn default() -> Self {
+        Self { origin: (148, 88) }
+    }
+}
+
+// A compiled animation. Everything but the script is in one blob, `data`, and
+// the offset fields point into it.
+pub struct BattleAnim {
+    pub data: Vec<u8>,
+    pub sections: usize,
+    pub oam_right: usize,
+    pub oam_left: usize,
+    pub palette: usize,
+    // The script, uncompressed. Its sheet pointers are offsets into `data`.
+    pub script: Vec<u8>,
+    // Where each sheet pointer is in `script`.
+    pub sheet_pointers: Vec<usize>,
+}
+
+impl BattleAnim {
+    // The script as an LZ77 stream of literal blocks, along with where each
+    // sheet pointer ended up in it. Script words never straddle a block, so
+    // every pointer's four bytes stay together.
+    pub fn script_stream(&self) -> (Vec<u8>, Vec<usize>) {
+        let mut stream = (0x10 | (self.script.len() as u32) << 8)
+            .to_le_bytes()
+            .to_vec();
+        for block in self.script.chunks(8) {
+            stream.push(0);
+            stream.extend(block);
+        }
+
+        let pointers = self
+            .sheet_pointers
+            .iter()
+            .map(|&i| 4 + i / 8 * 9 + 1 + i % 8)
+            .collect();
+        (stream, pointers)
+    }
+}
+
+struct Frame {
+    sheet: usize,
+    oam: usize,
+}
+
+pub fn compile(
+    modes: &[Mode],
+    frames: &HashMap<String, GBAImage>,
+    palette: &Palette,
+    opts: &BanimOptions,
+) -> Result<BattleAnim, Error> {
+    let sprite_opts = SpriteOptions {
+        mapping: Mapping::TwoD,
+        origin: opts.origin,
+        palette: 0,
+    };
+
+    let mut sheets: Vec<GBAImage> = Vec::new();
+    // Tile rows used in the last sheet.
+    let mut rows_used = 0;
+    let mut oam_right = Vec::new();
+    let mut oam_left = Vec::new();
+    let mut compiled: HashMap<&str, (usize, Frame)> = HashMap::new();
+
+    for (number, name) in frame_images(modes).into_iter().enumerate() {
+        let image = frames
+            .get(name)
+            .ok_or_else(|| Error::MissingFrame(name.to_string()))?;
+        let sprite = Sprite::from_image(image, &sprite_opts)?;
+
+        let rows = sprite.sheet.height / 8;
+        if rows > SHEET_TILES_HIGH {
+            return Err(Error::FrameTooLarge(name.to_string()));
+        }
+        if sheets.is_empty() || rows_used + rows > SHEET_TILES_HIGH {
+            sheets.push(GBAImage {
+                palette: palette.clone(),
+                width: 256,
+                height: SHEET_TILES_HIGH * 8,
+                data: vec![0; 256 * SHEET_TILES_HIGH * 8],
+            });
+            rows_used = 0;
+        }
+
+        // Sprite sheets are already the width of ours, so the frame's tiles
+        // are just shifted down.
+        let sheet = sheets.last_mut().unwrap();
+        let start = rows_used * 8 * sheet.width;
+        sheet.data[start..start + sprite.sheet.data.len()]
+            .copy_from_slice(&sprite.sheet.data);
+
+        let frame = Frame {
+            sheet: sheets.len() - 1,
+            oam: oam_right.len(),
+        };
+        for piece in sprite.pieces.iter() {
+            let [attr0, attr1, attr2] = piece.attributes(0);
+            let tile = attr2 + (rows_used * 32) as u16;
+            let width = (piece.shape.width * 8) as i32;
+
+            // Positions are stored separately from the attributes.
+            push_oam(
+                &mut oam_right,
+                [attr0 & !0xFF, attr1 & !0x1FF, tile],
+                piece.x,
+                piece.y,
+            );
+            push_oam(
+                &mut oam_left,
+                [attr0 & !0xFF, (attr1 & !0x1FF) | 0x1000, tile],
+                -piece.x - width,
+                piece.y,
+            );
+        }
+        for oam in [&mut oam_right, &mut oam_left] {
+            oam.extend(1u32.to_le_bytes());
+            oam.extend([0; 8]);
+        }
+
+        rows_used += rows;
+        compiled.insert(name, (number, frame));
+    }
+
+    let mut data = Vec::new();
+    let mut sheet_offsets = Vec::with_capacity(sheets.len());
+    for sheet in sheets.iter() {
+        sheet_offsets.push(data.len());
+        data.extend(compress(crate::encode_tiles(sheet.tiles())));
+        align(&mut data);
+    }
+
+    let mut script = Vec::new();
+    let mut sheet_pointers = Vec::new();
+    let mut sections = Vec::with_capacity(MODE_COUNT * 4);
+    for mode in modes {
+        sections.extend((script.len() as u32).to_le_bytes());
+
+        for command in mode.commands.iter() {
+            let words = match command {
+                Command::Command(cmd) => vec![0x8500_0000 | *cmd as u32],
+                Command::Sound(id) => vec![0x8500_0048 | (*id as u32) << 8],
+                Command::Frame { delay, image } => {
+                    let (number, frame) = &compiled[image.as_str()];
+                    sheet_pointers.push(script.len() + 4);
+                    vec![
+                        0x8600_0000 | (*number as u32) << 16 | *delay as u32,
+                        sheet_offsets[frame.sheet] as u32,
+                        frame.oam as u32,
+                    ]
+                }
+            };
+            for word in words {
+                script.extend(word.to_le_bytes());
+            }
+        }
+
+        script.extend(0x8000_0000u32.to_le_bytes());
+    }
+
+    // Every allegiance gets the same colors.
+    let mut palettes = palette.encode();
+    palettes.resize(32, 0);
+    let palettes = palettes.repeat(4);
+
+    let mut place = |blob: Vec<u8>| {
+        let offset = data.len();
+        data.extend(blob);
+        align(&mut data);
+        offset
+    };
+
+    let oam_right = place(compress(oam_right));
+    let oam_left = place(compress(oam_left));
+    let palette = place(compress(palettes));
+    let sections = place(sections);
+
+    Ok(BattleAnim {
+        data,
+        sections,
+        oam_right,
+        oam_left,
+        palette,
+        script,
+        sheet_pointers,
+    })
+}
+
+// One OAM entry: the three attributes, the position, and two bytes of
+// padding.
+fn push_oam(oam: &mut Vec<u8>, attrs: [u16; 3], x: i32, y: i32) {
+    for attr in attrs {
+        oam.extend(attr.to_le_bytes());
+    }
+    oam.extend((x as i16).to_le_bytes());
+    oam.extend((y as i16).to_le_bytes());
+    oam.extend([0; 2]);
+}
+
+fn compress(data: Vec<u8>) -> Vec<u8> {
+    gbalz77::compress(&data[..], CompressionStrategy::CheckAllCandidates)
+}
+
+fn align(data: &mut Vec<u8>) {
+    data.resize(data.len().next_multiple_of(4), 0);
+}
@@ -0,0 +1,477 @@
+// Text-mode background support.
+//
+// GBA text backgrounds store 4bpp tiles, and each tile picks one of 16
+// palette banks (of 16 colors each) through its tilemap entry. This lets a
+// single background use far more than 16 colors, as long as no individual
+// tile needs more than that.
+//
+// Index 0 of every bank is transparent in hardware, so anything drawn with it
+// shows whatever is behind the layer instead. We only give index 0 to pixels
+// that are meant to be transparent, which leaves 15 colors per bank for
+// everything else.
+//
+// Colors are grouped by what the GBA can actually show, so ones that only
+// differ in their low 3 bits share a palette slot.
+
+use std::collections::{BTreeSet, HashMap};
+
+use image::{GenericImageView, Pixel};
+
+use crate::diagnose::{Diagnosis, TileColors};
+use crate::{Color, Error, GBAImage, Palette, Transparency};
+
+pub const MAX_PALETTES: usize = 16;
+pub const COLORS_PER_PALETTE: usize = 16;
+
+pub struct MultiPaletteImage {
+    // The combined palette block has exactly `16 * palette_count` colors, and
+    // pixel indices are global (i.e. `16 * bank + local index`). Because
+    // `encode_tiles` only keeps the low nybble of each index, the tiles encode
+    // exactly as they would under their own bank.
+    pub image: GBAImage,
+    // INVARIANT: tile_palettes.len() = (width / 8) * (height / 8), row-major.
+    // INVARIANT: every pixel of tile `i` lies in bank `tile_palettes[i]`.
+    tile_palettes: Vec<usize>,
+}
+
+impl MultiPaletteImage {
+    pub fn validate(&self) -> Result<(), Error> {
+        let image = &self.image;
+
+        if image.data.len() != image.width * image.height {
+            return Err(Error::DimensionMismatch);
+        }
+
+        if !image.width.is_multiple_of(8) || !image.height.is_multiple_of(8) {
+            return Err(Error::BadDimensions(image.width, image.height));
+        }
+
+        // Banks past the 16th couldn't be reached by any pixel, so more than
+        // that is a broken palette block rather than too many colors.
+        if !image.palette.len().is_multiple_of(COLORS_PER_PALETTE)
+            || self.palette_count() > MAX_PALETTES
+        {
+            return Err(Error::BadPaletteBanks);
+        }
+
+        if self.tile_palettes.len() != (image.width / 8) * (image.height / 8) {
+            return Err(Error::DimensionMismatch);
+        }
+
+        let tiles_wide = image.width / 8;
+        let tiles = image.tiles().zip(self.tile_palettes.iter()).enumerate();
+        for (i, (tile, &bank)) in tiles {
+            if tile.pixels().any(|idx| idx / COLORS_PER_PALETTE != bank) {
+                let (x, y) = (i % tiles_wide, i / tiles_wide);
+                return Err(Error::TileOutsideBank(x, y, bank));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn palette_count(&self) -> usize {
+        self.image.palette.len() / COLORS_PER_PALETTE
+    }
+
+    pub fn palette(&self, bank: usize) -> Option<Palette> {
+        if bank >= self.palette_count() {
+            return None;
+        }
+
+        Some(
+            self.image.palette.0[bank * COLORS_PER_PALETTE..]
+                .iter()
+                .take(COLORS_PER_PALETTE)
+                .copied()
+                .collect(),
+        )
+    }
+
+    // The palette bank used by each tile, in the same order as
+    // `self.image.tiles()`.
+    pub fn tile_palettes(&self) -> &[usize] {
+        &self.tile_palettes[..]
+    }
+
+    // If [transparency] is given, transparent pixels get index 0 of their
+    // tile's bank, which holds the backdrop chosen by
+    // [Transparency::backdrop_for]. Otherwise index 0 goes unused.
+    pub fn from_generic_image<V>(
+        img: &V,
+        max_palettes: usize,
+        transparency: Option<Transparency>,
+    ) -> Result<Self, Error>
+    where
+        V: GenericImageView,
+        V::Pixel: Pixel,
+    {
+        let width = img.width() as usize;
+        let height = img.height() as usize;
+
+        if !width.is_multiple_of(8) || !height.is_multiple_of(8) {
+            return Err(Error::BadDimensions(width, height));
+        }
+
+        let max_palettes = max_palettes.min(MAX_PALETTES);
+        let tiles_wide = width / 8;
+        let tile_count = tiles_wide * (height / 8);
+
+        // Each pixel's color as the GBA stores it, or `None` if it's
+        // transparent.
+        let pixels = img
+            .pixels()
+            .map(|(x, y, pix)| {
+                let color = match transparency {
+                    Some(t) if t.is_transparent(&pix) => None,
+                    _ => Some(Color::from(pix).to_16bit()),
+                };
+                (x as usize, y as usize, color)
+            })
+            .collect::<Vec<_>>();
+
+        let mut tile_colors = vec![BTreeSet::new(); tile_count];
+        for &(x, y, color) in pixels.iter() {
+            if let Some(color) = color {
+                tile_colors[(y / 8) * tiles_wide + x / 8].insert(color);
+            }
+        }
+
+        let tile_palettes =
+            assign_banks(&tile_colors, tiles_wide, max_palettes)?;
+
+        let bank_count =
+            tile_palettes.iter().map(|bank| bank + 1).max().unwrap_or(1);
+        let mut banks = vec![BTreeSet::new(); bank_count];
+        for (&bank, colors) in tile_palettes.iter().zip(tile_colors) {
+            banks[bank].extend(colors);
+        }
+
+        let backdrop = transparency
+            .and_then(|t| t.backdrop_for(img))
+            .unwrap_or(Color::rgb(0, 0, 0));
+        let mut lookup: Vec<HashMap<u16, usize>> = Vec::new();
+        let mut palette = Vec::new();
+        for (bank, colors) in banks.into_iter().enumerate() {
+            let mut bank_lookup = HashMap::new();
+            palette.push(backdrop);
+            for (i, color) in colors.into_iter().enumerate() {
+                bank_lookup.insert(color, bank * COLORS_PER_PALETTE + i + 1);
+                palette.push(Color::from_16bit(color));
+            }
+            palette
+                .resize((bank + 1) * COLORS_PER_PALETTE, Color::rgb(0, 0, 0));
+            lookup.push(bank_lookup);
+        }
+
+        let data = pixels
+            .iter()
+            .map(|&(x, y, color)| {
+                let bank = tile_palettes[(y / 8) * tiles_wide + x / 8];
+                let idx = match color {
+                    Some(color) => lookup[bank][&color],
+                    None => bank * COLORS_PER_PALETTE,
+                };
+                idx as u8
+            })
+            .collect();
+
+        Ok(Self {
+            image: GBAImage {
+                palette: Palette::from(palette),
+                width,
+                height,
+                data,
+            },
+            tile_palettes,
+        })
+    }
+}
+
+// Greedily packs each tile's color set into as few banks as we can manage,
+// handling the most colorful tiles first. Returns the bank of each tile.
+fn assign_banks(
+    tile_colors: &[BTreeSet<u16>],
+    tiles_wide: usize,
+    max_palettes: usize,
+) -> Result<Vec<usize>, Error> {
+    // Index 0 of each bank is reserved for transparent pixels.
+    const CAPACITY: usize = COLORS_PER_PALETTE - 1;
+
+    let blame = |tiles: &[usize]| {
+        Error::TooManyColors(Diagnosis {
+            tiles: tiles
+                .iter()
+                .map(|&i| TileColors {
+                    x: i % tiles_wide,
+                    y: i / tiles_wide,
+                    colors: tile_colors[i].len(),
+                })
+                .collect(),
+            ..Default::default()
+        })
+    };
+
+    let overfull = (0..tile_colors.len())
+        .filter(|&i| tile_colors[i].len() > CAPACITY)
+        .collect::<Vec<_>>();
+    if !overfull.is_empty() {
+        return Err(blame(&overfull));
+    }
+
+    let mut order = (0..tile_colors.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(tile_colors[i].len()));
+
+    let mut banks: Vec<BTreeSet<u16>> = Vec::new();
+    let mut result = vec![0; tile_colors.len()];
+
+    for i in order {
+        let colors = &tile_colors[i];
+
+        let best = banks
+            .iter()
+            .enumerate()
+            .map(|(bank, existing)| {
+                (bank, colors.difference(existing).count() + existing.len())
+            })
+            .filter(|&(_, merged)| merged <= CAPACITY)
+            .min_by_key(|&(bank, merged)| (merged - banks[bank].len(), bank))
+            .map(|(bank, _)| bank);
+
+        let bank = match best {
+            Some(bank) => bank,
+            None if banks.len() < max_palettes => {
+                banks.push(BTreeSet::new());
+                banks.len() - 1
+            }
+            None => return Err(blame(&[i])),
+        };
+
+        banks[bank].extend(colors.iter().copied());
+        result[i] = bank;
+    }
+
+    Ok(result)
+}
+
+// Text BG tilemaps can only address 1024 tiles.
+pub const MAX_TILES: usize = 1024;
+
+// One 16-bit text BG tilemap entry.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScreenEntry {
+    pub tile: usize,
+    pub hflip: bool,
+    pub vflip: bool,
+    pub palette: usize,
+}
+
+impl ScreenEntry {
+    pub fn to_16bit(self) -> u16 {
+        ((self.tile & 0x3FF) as u16)
+            | ((self.hflip as u16) << 10)
+            | ((self.vflip as u16) << 11)
+            | (((self.palette & 0xF) as u16) << 12)
+    }
+}
+
+pub struct Tilemap {
+    // Dimensions are in tiles, not pixels.
+    pub width: usize,
+    pub height: usize,
+    // INVARIANT: entries.len() = width * height, row-major.
+    entries: Vec<ScreenEntry>,
+}
+
+impl Tilemap {
+    pub fn entry_at(&self, x: usize, y: usize) -> Option<ScreenEntry> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        self.entries.get(y * self.width + x).copied()
+    }
+
+    pub fn entries(&self) -> &[ScreenEntry] {
+        &self.entries[..]
+    }
+
+    // Maps whose sides are both multiples of 32 are written one 32x32
+    // screenblock at a time, which is how the hardware expects 512px-wide or
+    // -tall maps to be laid out. Anything else is written row-major.
+    pub fn encode(&self) -> Vec<u8> {
+        const BLOCK: usize = 32;
+
+        let order: Box<dyn Iterator<Item = (usize, usize)>> = if self
+            .width
+            .is_multiple_of(BLOCK)
+            && self.height.is_multiple_of(BLOCK)
+        {
+            let width = self.width;
+            Box::new(
+                (0..self.height / BLOCK)
+                    .flat_map(move |by| {
+                        (0..width / BLOCK).map(move |bx| (bx, by))
+                    })
+                    .flat_map(|(bx, by)| {
+                        (0..BLOCK).flat_map(move |y| {
+                            (0..BLOCK)
+                                .map(move |x| (bx * BLOCK + x, by * BLOCK + y))
+                        })
+                    }),
+            )
+        } else {
+            let width = self.width;
+            Box::new(
+                (0..self.height)
+                    .flat_map(move |y| (0..width).map(move |x| (x, y))),
+            )
+        };
+
+        order
+            .map(|(x, y)| self.entries[y * self.width + x])
+            .flat_map(|entry| entry.to_16bit().to_le_bytes())
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TilemapOptions {
+    // Also match tiles that are horizontal and/or vertical mirrors of each
+    // other.
+    pub flips: bool,
+    // Size of the output map, in tiles. If larger than the image, the extra
+    // space is filled with a blank tile. Defaults to the size of the image.
+    pub size: Option<(usize, usize)>,
+}
+
+impl Default for TilemapOptions {
+    fn default() -> Self {
+        Self {
+            flips: true,
+            size: None,
+        }
+    }
+}
+
+// Deduplicates the tiles of [image], returning the unique tiles (as a single
+// 8px-wide column, so `tiles()` yields them in tile-number order) along with a
+// tilemap that rebuilds the original image from them.
+//
+// Tiles are compared by their 4bpp values, so two tiles with the same shape
+// but in different palette banks share tile data. If [tile_palettes] is
+// absent, every tile uses bank 0.
+pub fn build_tilemap(
+    image: &GBAImage,
+    tile_palettes: Option<&[usize]>,
+    opts: &TilemapOptions,
+) -> Result<(GBAImage, Tilemap), Error> {
+    if !image.width.is_multiple_of(8) || !image.height.is_multiple_of(8) {
+        return Err(Error::BadDimensions(image.width, image.height));
+    }
+
+    let tiles_wide = image.width / 8;
+    let tiles_high = image.height / 8;
+    let (width, height) = opts.size.unwrap_or((tiles_wide, tiles_high));
+
+    if width < tiles_wide || height < tiles_high {
+        return Err(Error::BadMapSize);
+    }
+
+    let mut seen: HashMap<Vec<u8>, usize> = HashMap::new();
+    let mut data: Vec<u8> = Vec::new();
+    let mut entries = Vec::with_capacity(tiles_wide * tiles_high);
+
+    for (i, tile) in image.tiles().enumerate() {
+        let pixels = tile.indices().collect::<Vec<_>>();
+        let key = pixels.iter().map(|idx| idx & 0xF).collect::<Vec<_>>();
+        let palette = tile_palettes.map_or(0, |banks| banks[i]);
+
+        let variants = if opts.flips {
+            vec![(false, false), (true, false), (false, true), (true, true)]
+        } else {
+            vec![(false, false)]
+        };
+
+        let found = variants.into_iter().find_map(|(hflip, vflip)| {
+            seen.get(&flip_tile(&key, hflip, vflip))
+                .map(|&tile| ScreenEntry {
+                    tile,
+                    hflip,
+                    vflip,
+                    palette,
+                })
+        });
+
+        let entry = match found {
+            Some(entry) => entry,
+            None => {
+                let tile = seen.len();
+                seen.insert(key, tile);
+                data.extend(pixels);
+                ScreenEntry {
+                    tile,
+                    hflip: false,
+                    vflip: false,
+                    palette,
+                }
+            }
+        };
+
+        entries.push(entry);
+    }
+
+    let entries = if (width, height) == (tiles_wide, tiles_high) {
+        entries
+    } else {
+        let blank = match seen.get(&vec![0; 64]) {
+            Some(&tile) => tile,
+            None => {
+                data.extend(std::iter::repeat_n(0, 64));
+                seen.len()
+            }
+        };
+        let fill = ScreenEntry {
+            tile: blank,
+            ..Default::default()
+        };
+
+        let mut padded = vec![fill; width * height];
+        for (i, entry) in entries.into_iter().enumerate() {
+            padded[(i / tiles_wide) * width + i % tiles_wide] = entry;
+        }
+        padded
+    };
+
+    let tile_count = data.len() / 64;
+    if tile_count > MAX_TILES {
+        return Err(Error::TooManyTiles);
+    }
+
+    let sheet = GBAImage {
+        palette: image.palette.clone(),
+        width: 8,
+        height: 8 * tile_count,
+        data,
+    };
+
+    Ok((
+        sheet,
+        Tilemap {
+            width,
+            height,
+            entries,
+        },
+    ))
+}
+
+fn flip_tile(pixels: &[u8], hflip: bool, vflip: bool) -> Vec<u8> {
+    (0..8)
+        .flat_map(|y| (0..8).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let x = if hflip { 7 - x } else { x };
+            let y = if vflip { 7 - y } else { y };
+            pixels[y * 8 + x]
+        })
+        .collect()
+}
@@ -0,0 +1,136 @@
+// Fire Emblem map tilesets ("chipsets").
+//
+// Maps are built out of 16x16 metatiles, each made of four 8x8 tiles that can
+// be flipped and use any of the map's 5 palettes. A tileset is three assets,
+// each referenced by ID from the chapter data:
+//   * the object set: the 8x8 tiles themselves, split into two halves of up
+//     to 512 tiles (obj1 and obj2), each LZ77-compressed;
+//   * the palette group: the 5 palettes, back to back;
+//   * the tile config: for each of the 1024 metatiles, its four screen entries
+//     (top left, top right, bottom left, bottom right), followed by a terrain
+//     type for each metatile. This is LZ77-compressed.
+//
+// Map data refers to metatiles by their index in the tileset image, read
+// row-major.
+
+use gbalz77::CompressionStrategy;
+use image::{GenericImageView, Pixel};
+
+use crate::bg::{self, MultiPaletteImage, ScreenEntry, TilemapOptions};
+use crate::{Error, GBAImage, Palette, Transparency};
+
+pub const MAX_METATILES: usize = 1024;
+pub const MAX_PALETTES: usize = 5;
+// Tiles per object set half.
+pub const OBJ_TILES: usize = 512;
+
+pub struct Chipset {
+    // Unique 8x8 tiles, in a single 8px-wide column.
+    pub tiles: GBAImage,
+    // The palette group, `16 * palette_count` colors.
+    pub palettes: Palette,
+    // Entries use palette banks 0 to 4; see [Chipset::encode_config].
+    pub metatiles: Vec<[ScreenEntry; 4]>,
+}
+
+impl Chipset {
+    // Map layers above the bottom one show through wherever index 0 is used,
+    // so only pixels [transparency] matches get it; see
+    // [MultiPaletteImage::from_generic_image].
+    pub fn from_image<V>(
+        img: &V,
+        transparency: Option<Transparency>,
+    ) -> Result<Self, Error>
+    where
+        V: GenericImageView,
+        V::Pixel: Pixel,
+    {
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        if !width.is_multiple_of(16) || !height.is_multiple_of(16) {
+            return Err(Error::BadTilesetDimensions);
+        }
+
+        let metatiles_wide = width / 16;
+        let metatiles_high = height / 16;
+        if metatiles_wide * metatiles_high > MAX_METATILES {
+            return Err(Error::TooManyTiles);
+        }
+
+        let image = MultiPaletteImage::from_generic_image(
+            img,
+            MAX_PALETTES,
+            transparency,
+        )?;
+        image.validate()?;
+
+        let (tiles, tilemap) = bg::build_tilemap(
+            &image.image,
+            Some(image.tile_palettes()),
+            &TilemapOptions::default(),
+        )?;
+
+        let metatiles = (0..metatiles_high)
+            .flat_map(|y| (0..metatiles_wide).map(move |x| (x * 2, y * 2)))
+            .map(|(x, y)| {
+                [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+                    .map(|(x, y)| tilemap.entry_at(x, y).unwrap())
+            })
+            .collect();
+
+        Ok(Self {
+            palettes: image.image.palette.clone(),
+            tiles,
+            metatiles,
+        })
+    }
+
+    // The (compressed) object set, as (obj1, obj2). obj2 is `None` if every
+    // tile fits in obj1.
+    pub fn encode_obj(&self) -> (Vec<u8>, Option<Vec<u8>>) {
+        let mut obj1 = crate::encode_tiles(self.tiles.tiles());
+        let obj2 = obj1.split_off(obj1.len().min(OBJ_TILES * 32));
+        let obj2 = (!obj2.is_empty()).then(|| compress(obj2));
+        (compress(obj1), obj2)
+    }
+
+    // The game always loads a full group of [MAX_PALETTES] palettes, so
+    // unused banks are padded out with black.
+    pub fn encode_palettes(&self) -> Vec<u8> {
+        let mut palettes = self.palettes.encode();
+        palettes.resize(MAX_PALETTES * 16 * 2, 0);
+        palettes
+    }
+
+    // The (compressed) tile config. Palette numbers in screen entries are
+    // offset by [palette_base], the first BG palette bank the game loads the
+    // palette group into. [terrain] gives each metatile's terrain type;
+    // metatiles past its end get terrain 0.
+    pub fn encode_config(
+        &self,
+        terrain: &[u8],
+        palette_base: usize,
+    ) -> Vec<u8> {
+        let mut config = Vec::with_capacity(MAX_METATILES * 9);
+
+        for i in 0..MAX_METATILES {
+            let entries = self.metatiles.get(i).copied().unwrap_or_default();
+            for entry in entries {
+                let entry = ScreenEntry {
+                    palette: entry.palette + palette_base,
+                    ..entry
+                };
+                config.extend(entry.to_16bit().to_le_bytes());
+            }
+        }
+
+        config.extend(
+            (0..MAX_METATILES).map(|i| terrain.get(i).copied().unwrap_or(0)),
+        );
+
+        compress(config)
+    }
+}
+
+fn compress(data: Vec<u8>) -> Vec<u8> {
+    gbalz77::compress(&data[..], CompressionStrategy::CheckAllCandidates)
+}
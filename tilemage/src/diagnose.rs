@@ -0,0 +1,213 @@
+// Detail for color and dimension errors, so artists can be pointed at exactly
+// which parts of an image are at fault.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use image::{GenericImageView, Pixel, Rgb, RgbImage};
+
+use crate::{Color, Preview};
+
+// How many colors and tiles to name before summarizing the rest.
+const LISTED: usize = 4;
+
+const TILE_HIGHLIGHT: Rgb<u8> = Rgb([0xFF, 0, 0]);
+const PIXEL_HIGHLIGHT: Rgb<u8> = Rgb([0xFF, 0, 0xFF]);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnosis {
+    // Offending colors, each with the first pixel using it, as (x, y), in
+    // order of first appearance.
+    pub colors: Vec<(Color, (usize, usize))>,
+    // Every offending pixel, as (x, y).
+    pub pixels: Vec<(usize, usize)>,
+    // Offending 8x8 tiles, row-major.
+    pub tiles: Vec<TileColors>,
+}
+
+// An 8x8 tile, by tile coordinates, and how many colors it uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileColors {
+    pub x: usize,
+    pub y: usize,
+    pub colors: usize,
+}
+
+impl Diagnosis {
+    // Blames [pixels] (as (x, y, color)) and the tiles containing them.
+    // [color_at] gives the color of any pixel in a [width]x[height] image,
+    // for counting each tile's colors.
+    pub(crate) fn from_pixels(
+        width: usize,
+        height: usize,
+        pixels: Vec<(usize, usize, Color)>,
+        color_at: impl Fn(usize, usize) -> Color,
+    ) -> Self {
+        let mut seen = HashMap::new();
+        let mut colors = Vec::new();
+        for &(x, y, color) in &pixels {
+            seen.entry(color)
+                .or_insert_with(|| colors.push((color, (x, y))));
+        }
+
+        let tiles = pixels
+            .iter()
+            .map(|&(x, y, _)| (y / 8, x / 8))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|(ty, tx)| tile_colors(width, height, tx, ty, &color_at))
+            .collect();
+
+        Self {
+            colors,
+            pixels: pixels.into_iter().map(|(x, y, _)| (x, y)).collect(),
+            tiles,
+        }
+    }
+
+    // Like [Diagnosis::from_pixels], but counts the colors of every tile,
+    // most colorful first. For when the colors are only at fault together,
+    // so the tiles holding [pixels] are no more to blame than any other.
+    pub(crate) fn with_every_tile(
+        width: usize,
+        height: usize,
+        pixels: Vec<(usize, usize, Color)>,
+        color_at: impl Fn(usize, usize) -> Color,
+    ) -> Self {
+        let mut tiles = (0..height.div_ceil(8))
+            .flat_map(|ty| (0..width.div_ceil(8)).map(move |tx| (tx, ty)))
+            .map(|(tx, ty)| tile_colors(width, height, tx, ty, &color_at))
+            .collect::<Vec<_>>();
+        tiles.sort_by_key(|tile| std::cmp::Reverse(tile.colors));
+
+        Self {
+            tiles,
+            ..Self::from_pixels(width, height, pixels, color_at)
+        }
+    }
+
+    // Blames the strip of pixels past the last whole tile.
+    pub fn for_dimensions(width: usize, height: usize) -> Self {
+        let (whole_w, whole_h) = (width / 8 * 8, height / 8 * 8);
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| x >= whole_w || y >= whole_h)
+            .collect();
+
+        Self {
+            pixels,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+            && self.pixels.is_empty()
+            && self.tiles.is_empty()
+    }
+
+    // A dimmed copy of [img], its colors shown as [preview] says, with
+    // offending tiles outlined in red and offending pixels in magenta.
+    pub fn render<V>(&self, img: &V, preview: Preview) -> RgbImage
+    where
+        V: GenericImageView,
+        V::Pixel: Pixel,
+    {
+        let mut out = RgbImage::from_fn(img.width(), img.height(), |x, y| {
+            let Color { r, g, b } =
+                Color::from(img.get_pixel(x, y)).preview(preview);
+            Rgb([r / 3, g / 3, b / 3])
+        });
+        let (width, height) = (out.width() as usize, out.height() as usize);
+
+        for tile in &self.tiles {
+            let (left, top) = (tile.x * 8, tile.y * 8);
+            let right = (left + 7).min(width.saturating_sub(1));
+            let bottom = (top + 7).min(height.saturating_sub(1));
+            for y in top..=bottom {
+                for x in left..=right {
+                    if x == left || x == right || y == top || y == bottom {
+                        out.put_pixel(x as u32, y as u32, TILE_HIGHLIGHT);
+                    }
+                }
+            }
+        }
+
+        for &(x, y) in &self.pixels {
+            if x < width && y < height {
+                out.put_pixel(x as u32, y as u32, PIXEL_HIGHLIGHT);
+            }
+        }
+
+        out
+    }
+}
+
+fn tile_colors(
+    width: usize,
+    height: usize,
+    tx: usize,
+    ty: usize,
+    color_at: impl Fn(usize, usize) -> Color,
+) -> TileColors {
+    let used = (ty * 8..(ty * 8 + 8).min(height))
+        .flat_map(|y| (tx * 8..(tx * 8 + 8).min(width)).map(move |x| (x, y)))
+        .map(|(x, y)| color_at(x, y))
+        .collect::<BTreeSet<_>>();
+    TileColors {
+        x: tx,
+        y: ty,
+        colors: used.len(),
+    }
+}
+
+// Written to follow an error message, so it's empty or starts with ": ".
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+
+        if !self.colors.is_empty() {
+            let mut listed = self
+                .colors
+                .iter()
+                .take(LISTED)
+                .map(|(c, (x, y))| {
+                    format!(
+                        "#{:02X}{:02X}{:02X} at ({}, {})",
+                        c.r, c.g, c.b, x, y
+                    )
+                })
+                .collect::<Vec<_>>();
+            if self.colors.len() > LISTED {
+                listed.push(format!("{} more", self.colors.len() - LISTED));
+            }
+            parts.push(listed.join(", "));
+        }
+
+        if !self.tiles.is_empty() {
+            let mut listed = self
+                .tiles
+                .iter()
+                .take(LISTED)
+                .map(|t| {
+                    let plural = if t.colors == 1 { "" } else { "s" };
+                    format!(
+                        "tile ({}, {}) uses {} color{}",
+                        t.x, t.y, t.colors, plural
+                    )
+                })
+                .collect::<Vec<_>>();
+            if self.tiles.len() > LISTED {
+                listed
+                    .push(format!("{} more tiles", self.tiles.len() - LISTED));
+            }
+            parts.push(listed.join(", "));
+        }
+
+        if parts.is_empty() {
+            Ok(())
+        } else {
+            write!(f, ": {}", parts.join("; "))
+        }
+    }
+}
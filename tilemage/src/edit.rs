@@ -0,0 +1,173 @@
+// Building and editing images directly, by palette index.
+//
+// Everything here keeps [GBAImage]'s invariants: every pixel stays within the
+// palette, and the data always covers exactly width * height pixels. Anything
+// that would break them fails instead, leaving the image as it was.
+
+use crate::{Error, GBAImage, GBAImageView, Palette};
+
+impl GBAImage {
+    // An image from row-major pixel indices into [palette].
+    pub fn from_indices(
+        width: usize,
+        height: usize,
+        data: Vec<u8>,
+        palette: Palette,
+    ) -> Result<Self, Error> {
+        if data.len() != width * height {
+            return Err(Error::IndexCount(data.len(), width, height));
+        }
+        if let Some(&idx) =
+            data.iter().find(|&&idx| idx as usize >= palette.len())
+        {
+            return Err(Error::IndexOutOfRange(idx as usize, palette.len()));
+        }
+
+        Ok(Self {
+            palette,
+            width,
+            height,
+            data,
+        })
+    }
+
+    // An image filled with index 0.
+    pub fn blank(
+        width: usize,
+        height: usize,
+        palette: Palette,
+    ) -> Result<Self, Error> {
+        Self::from_indices(width, height, vec![0; width * height], palette)
+    }
+
+    pub fn set_pixel(
+        &mut self,
+        x: usize,
+        y: usize,
+        idx: usize,
+    ) -> Result<(), Error> {
+        if x >= self.width || y >= self.height {
+            return Err(Error::OutOfBounds(x, y));
+        }
+
+        self.data[y * self.width + x] = self.check_index(idx)?;
+        Ok(())
+    }
+
+    // Copies [src]'s pixel indices (not colors) into this image, with its top
+    // left corner at ([x], [y]).
+    pub fn blit(
+        &mut self,
+        src: &GBAImageView,
+        x: usize,
+        y: usize,
+    ) -> Result<(), Error> {
+        if x + src.width > self.width || y + src.height > self.height {
+            return Err(Error::OutOfBounds(x + src.width, y + src.height));
+        }
+
+        let rows = src.rows().collect::<Vec<_>>();
+        if rows.len() != src.height || rows.iter().any(|r| r.len() != src.width)
+        {
+            return Err(Error::OutOfBounds(
+                src.x + src.width,
+                src.y + src.height,
+            ));
+        }
+        for &idx in rows.iter().copied().flatten() {
+            self.check_index(idx as usize)?;
+        }
+
+        for (row, line) in rows.into_iter().enumerate() {
+            let start = (y + row) * self.width + x;
+            self.data[start..start + line.len()].copy_from_slice(line);
+        }
+        Ok(())
+    }
+
+    // Swaps two palette entries, and the pixels using them, so the image
+    // looks the same afterwards.
+    pub fn swap_palette_entries(
+        &mut self,
+        a: usize,
+        b: usize,
+    ) -> Result<(), Error> {
+        let (a, b) = (self.check_index(a)?, self.check_index(b)?);
+
+        self.palette.0.swap(a as usize, b as usize);
+        for idx in self.data.iter_mut() {
+            if *idx == a {
+                *idx = b;
+            } else if *idx == b {
+                *idx = a;
+            }
+        }
+        Ok(())
+    }
+
+    // Cuts the image down to the given rectangle.
+    pub fn crop(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), Error> {
+        if x + width > self.width || y + height > self.height {
+            return Err(Error::OutOfBounds(x + width, y + height));
+        }
+
+        *self = self.view(x, y, width, height).to_image();
+        Ok(())
+    }
+
+    // Grows the image by the given number of pixels on each side, filling the
+    // new space with index 0.
+    pub fn pad(
+        &mut self,
+        left: usize,
+        top: usize,
+        right: usize,
+        bottom: usize,
+    ) -> Result<(), Error> {
+        let mut padded = Self::blank(
+            left + self.width + right,
+            top + self.height + bottom,
+            self.palette.clone(),
+        )?;
+        padded.blit(&self.view(0, 0, self.width, self.height), left, top)?;
+
+        *self = padded;
+        Ok(())
+    }
+
+    // [idx] as stored, if it's a valid index into the palette.
+    fn check_index(&self, idx: usize) -> Result<u8, Error> {
+        // Entries past 256 exist, but no pixel can refer to them.
+        let limit = self.palette.len().min(1 << 8);
+        if idx >= limit {
+            return Err(Error::IndexOutOfRange(idx, limit));
+        }
+        Ok(idx as u8)
+    }
+}
+
+impl GBAImageView<'_> {
+    // Copies the view out into an image of its own, with the same palette.
+    // Any part of the view past the edge of its image is left at index 0.
+    pub fn to_image(&self) -> GBAImage {
+        let mut data = vec![0; self.width * self.height];
+        for (y, row) in data.chunks_mut(self.width.max(1)).enumerate() {
+            for (x, idx) in row.iter_mut().enumerate() {
+                *idx = self.pixel_at(x, y).unwrap_or(0) as u8;
+            }
+        }
+
+        GBAImage {
+            palette: self.owner.palette.clone(),
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// Fonts: sheets of glyphs, one per fixed-size cell.
+//
+// Fire Emblem's dialogue and menu fonts are 16x16 cells at 2bpp, stored a row
+// at a time (so each row is one little-endian word), with a width byte per
+// glyph giving how far to advance after drawing it. Some UI fonts are 1bpp
+// instead, or are stored as ordinary tiles.
+
+use crate::{BitDepth, Error, GBAImage, GBAImageView};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GlyphLayout {
+    // Each row of the cell in turn, the way FE's text glyphs are stored.
+    #[default]
+    Rows,
+    // The cell's 8x8 tiles, row-major.
+    Tiles,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FontOptions {
+    // Size of each glyph's cell, in pixels.
+    pub cell_width: usize,
+    pub cell_height: usize,
+    // Character code of the sheet's first cell. The rest follow row-major.
+    pub first: u32,
+    pub depth: BitDepth,
+    pub layout: GlyphLayout,
+    // Blank columns to leave after each glyph.
+    pub spacing: usize,
+    // Width of glyphs with nothing drawn in them, like the space.
+    pub blank_width: usize,
+}
+
+impl Default for FontOptions {
+    fn default() -> Self {
+        Self {
+            cell_width: 16,
+            cell_height: 16,
+            first: 0x20,
+            depth: BitDepth::Two,
+            layout: GlyphLayout::Rows,
+            spacing: 1,
+            blank_width: 4,
+        }
+    }
+}
+
+pub struct Glyph<'img> {
+    pub code: u32,
+    // How many pixels to advance after drawing the glyph.
+    pub width: usize,
+    pub image: GBAImageView<'img>,
+}
+
+// Slices [sheet] into glyphs, row-major, measuring each one up to its
+// rightmost column with anything drawn in it.
+pub fn glyphs<'img>(
+    sheet: &'img GBAImage,
+    opts: &FontOptions,
+) -> Result<Vec<Glyph<'img>>, Error> {
+    let (cell_width, cell_height) = (opts.cell_width, opts.cell_height);
+    if cell_width == 0
+        || cell_height == 0
+        || !cell_width.is_multiple_of(8)
+        || !cell_height.is_multiple_of(8)
+    {
+        return Err(Error::BadGlyphCell(cell_width, cell_height));
+    }
+    if !sheet.width.is_multiple_of(cell_width)
+        || !sheet.height.is_multiple_of(cell_height)
+    {
+        return Err(Error::BadGlyphSheet(
+            sheet.width,
+            sheet.height,
+            cell_width,
+            cell_height,
+        ));
+    }
+    sheet.validate_with_depth(opts.depth)?;
+
+    let columns = sheet.width / cell_width;
+    let rows = sheet.height / cell_height;
+    Ok((0..rows * columns)
+        .map(|i| {
+            let image = sheet.view(
+                (i % columns) * cell_width,
+                (i / columns) * cell_height,
+                cell_width,
+                cell_height,
+            );
+            let drawn = (0..cell_width).rev().find(|&x| {
+                (0..cell_height).any(|y| image.pixel_at(x, y) != Some(0))
+            });
+            Glyph {
+                code: opts.first + i as u32,
+                width: match drawn {
+                    Some(x) => x + 1 + opts.spacing,
+                    None => opts.blank_width,
+                },
+                image,
+            }
+        })
+        .collect())
+}
+
+impl Glyph<'_> {
+    pub fn encode(&self, depth: BitDepth, layout: GlyphLayout) -> Vec<u8> {
+        match layout {
+            GlyphLayout::Rows => {
+                crate::pack_indices(self.image.indices(), depth)
+            }
+            GlyphLayout::Tiles => {
+                let tiles = (0..self.image.height / 8).flat_map(|y| {
+                    (0..self.image.width / 8)
+                        .map(move |x| self.image.view(x * 8, y * 8, 8, 8))
+                });
+                crate::encode_tiles_with_depth(tiles, depth)
+            }
+        }
+    }
+}
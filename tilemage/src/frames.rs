@@ -0,0 +1,119 @@
+// Multi-frame (animated GIF and APNG) input.
+//
+// Every frame of an animation has to share one palette, so rather than
+// converting frames one at a time we stack them into a single sheet, one
+// frame below the next, and convert that. As long as frames are a whole
+// number of tiles tall, the sheet's tiles are each frame's tiles in turn.
+
+use std::io::Cursor;
+
+use image::codecs::{gif::GifDecoder, png::PngDecoder};
+use image::{AnimationDecoder, GenericImage, ImageFormat, RgbaImage};
+
+use crate::{ConvertOptions, Error, GBAImage, Palette};
+
+pub struct Frame {
+    pub image: RgbaImage,
+    // How long the frame is shown for, in milliseconds.
+    pub delay_ms: u32,
+}
+
+impl Frame {
+    // The delay in GBA frames (1/60 s), rounded to the nearest and at least 1.
+    pub fn delay_frames(&self) -> u32 {
+        ((self.delay_ms * 60 + 500) / 1000).max(1)
+    }
+}
+
+// Decodes every frame of an animated GIF or APNG. Any other image (including
+// a PNG that isn't animated) comes back as a single frame with no delay.
+pub fn decode_frames(
+    buf: &[u8],
+    format: Option<ImageFormat>,
+) -> Result<Vec<Frame>, Error> {
+    let format = crate::resolve_format(buf, format)?;
+
+    let frames = match format {
+        ImageFormat::Gif => GifDecoder::new(Cursor::new(buf))?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(Cursor::new(buf))?;
+            if !decoder.is_apng()? {
+                return Ok(vec![still(buf, format)?]);
+            }
+            decoder.apng()?.into_frames()
+        }
+        _ => return Ok(vec![still(buf, format)?]),
+    };
+
+    frames
+        .map(|frame| {
+            let frame = frame?;
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            Ok(Frame {
+                delay_ms: numer / denom.max(1),
+                image: frame.into_buffer(),
+            })
+        })
+        .collect()
+}
+
+fn still(buf: &[u8], format: ImageFormat) -> Result<Frame, Error> {
+    Ok(Frame {
+        image: crate::decode_image(buf, Some(format))?.to_rgba8(),
+        delay_ms: 0,
+    })
+}
+
+// Converts [frames] with one shared palette, as a sheet with each frame below
+// the last. Frames must all be the same size, a whole number of tiles.
+pub fn convert_frames(
+    frames: &[Frame],
+    opts: &ConvertOptions,
+) -> Result<GBAImage, Error> {
+    let (width, height) = match frames.first() {
+        Some(frame) => frame.image.dimensions(),
+        None => return Err(Error::NoFrames),
+    };
+    if !width.is_multiple_of(8) || !height.is_multiple_of(8) {
+        return Err(Error::BadDimensions(width as usize, height as usize));
+    }
+
+    let mut sheet = RgbaImage::new(width, height * frames.len() as u32);
+    for (i, frame) in frames.iter().enumerate() {
+        if frame.image.dimensions() != (width, height) {
+            return Err(Error::FrameSizeMismatch(i));
+        }
+        sheet.copy_from(&frame.image, 0, height * i as u32)?;
+    }
+
+    GBAImage::convert(&sheet, opts)
+}
+
+// Infers one palette for [images], which needn't be the same size, as if they
+// were a single image: every pixel of the first in scan order, then every
+// pixel of the next, and so on. Nothing is checked against `opts.depth`.
+pub fn shared_palette(
+    images: &[RgbaImage],
+    opts: &ConvertOptions,
+) -> Result<Palette, Error> {
+    let pixels: usize = images.iter().map(|image| image.pixels().len()).sum();
+    if pixels == 0 {
+        return Err(Error::NoFrames);
+    }
+
+    // A single row, so there's no padding to worry about.
+    let mut strip = RgbaImage::new(pixels as u32, 1);
+    let mut x = 0;
+    for image in images {
+        for pix in image.pixels() {
+            strip.put_pixel(x, 0, *pix);
+            x += 1;
+        }
+    }
+
+    let opts = ConvertOptions {
+        palette: None,
+        ..opts.clone()
+    };
+    Ok(GBAImage::convert(&strip, &opts)?.palette)
+}
@@ -0,0 +1,90 @@
+// Fire Emblem icons (items, skills, affinities, ...).
+//
+// Icons are 16x16 and stored as their four 8x8 tiles (top left, top right,
+// bottom left, bottom right), 128 bytes each. Every icon in a set shares one
+// 16-color palette.
+
+use crate::{Error, GBAImage, GBAImageView};
+
+pub const ICON_SIZE: usize = 16;
+
+// Size of one encoded icon, in bytes.
+pub const ICON_BYTES: usize = 4 * 32;
+
+// Slices [sheet] into icons, row-major. Every icon has to stay within the
+// first 16 colors of the palette; see [compact] for sheets converted at 8bpp.
+pub fn icons(sheet: &GBAImage) -> Result<Vec<GBAImageView<'_>>, Error> {
+    if !sheet.width.is_multiple_of(ICON_SIZE)
+        || !sheet.height.is_multiple_of(ICON_SIZE)
+    {
+        return Err(Error::BadIconSheetDimensions);
+    }
+
+    let icons = icons_of(sheet).collect::<Vec<_>>();
+
+    // Anything past index 15 would be cut down to its low nybble, and come
+    // out as some other color.
+    if let Some(i) = icons
+        .iter()
+        .position(|icon| icon.pixels().any(|idx| idx >= 16))
+    {
+        return Err(Error::IconIndex(i));
+    }
+
+    Ok(icons)
+}
+
+// Renumbers [sheet]'s colors in the order its icons bring them in, keeping
+// index 0 where it is, and drops the palette down to the colors in use.
+// Converting at 8bpp and then compacting lets us say which icon is at fault,
+// rather than just that the sheet as a whole has too many colors: indices
+// follow scan order (or the PNG's own palette), so whichever icon holds index
+// 16 isn't necessarily to blame; the first to bring in a 17th color is.
+pub fn compact(sheet: &mut GBAImage) -> Result<(), Error> {
+    let mut order = vec![0];
+    let mut blame = Vec::new();
+    for (i, icon) in icons_of(sheet).enumerate() {
+        for idx in icon.pixels() {
+            if !order.contains(&idx) {
+                order.push(idx);
+                blame.push(i);
+            }
+        }
+    }
+    if order.len() > 16 {
+        return Err(Error::IconColors(blame[15]));
+    }
+
+    let mut renumber = [0; 256];
+    for (new, &old) in order.iter().enumerate() {
+        renumber[old] = new as u8;
+    }
+    for idx in sheet.data.iter_mut() {
+        *idx = renumber[*idx as usize];
+    }
+    sheet.palette = order
+        .iter()
+        .filter_map(|&old| sheet.palette.lookup(old))
+        .collect();
+
+    Ok(())
+}
+
+fn icons_of(sheet: &GBAImage) -> impl Iterator<Item = GBAImageView<'_>> {
+    (0..sheet.height / ICON_SIZE)
+        .flat_map(|y| (0..sheet.width / ICON_SIZE).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            sheet.view(x * ICON_SIZE, y * ICON_SIZE, ICON_SIZE, ICON_SIZE)
+        })
+}
+
+// Whether [icon] is all index 0, like the cells padding out a sheet's grid.
+pub fn is_blank(icon: &GBAImageView) -> bool {
+    icon.pixels().all(|idx| idx == 0)
+}
+
+pub fn encode_icon(icon: &GBAImageView) -> Vec<u8> {
+    let tiles =
+        [(0, 0), (8, 0), (0, 8), (8, 8)].map(|(x, y)| icon.view(x, y, 8, 8));
+    crate::encode_tiles(tiles.into_iter())
+}
@@ -1,7 +1,8 @@
 // Generic GBA image manipulation library.
 //
-// TODO: Right now, we constrain `Subpixel = u8` in many cases. We should
-// instead try to convert from wider depths by trimming the LSBs.
+// Images with more than 8 bits per channel are accepted everywhere, but only
+// the top 8 bits of each channel are kept (and only the top 5 of those survive
+// conversion to GBA colors anyway).
 
 use std::{
     collections::{HashMap, HashSet},
@@ -11,35 +12,158 @@ use std::{
 };
 
 use image::{
-    guess_format, GenericImageView, ImageFormat, ImageReader, Pixel, Rgb,
+    guess_format, DynamicImage, GenericImageView, ImageBuffer, ImageFormat,
+    ImageReader, Pixel, Primitive, Rgb, Rgba,
 };
 use itertools::Itertools;
 use thiserror::Error;
 
+use diagnose::Diagnosis;
+
+pub mod affine;
+pub mod arrange;
+pub mod banim;
+pub mod bg;
+pub mod chipset;
+pub mod diagnose;
+mod edit;
+pub mod font;
+pub mod frames;
+pub mod icons;
+pub mod obj;
+mod palanim;
+pub mod palfile;
+pub mod portrait;
+pub mod quantize;
+
+#[cfg(test)]
+mod tests;
+
 #[derive(Error, Debug)]
 pub enum Error {
     // Errors that can come from trying to insert/format a bad image
-    #[error("image has too many colors")]
-    TooManyColors,
-    #[error("image contains a color not in the provided palette")]
-    UnknownColor,
-    #[error("width and height must be multiples of 8")]
-    BadDimensions,
+    #[error("image has too many colors{0}")]
+    TooManyColors(Diagnosis),
+    #[error("image contains colors not in the provided palette{0}")]
+    UnknownColor(Diagnosis),
+    #[error("image is {0}x{1}, but width and height must be multiples of 8")]
+    BadDimensions(usize, usize),
+    #[error("map size is unsupported or too small for the image")]
+    BadMapSize,
+    #[error("tile arrangement doesn't fit the image")]
+    BadArrangement,
+    #[error("sheet must be exactly {0}x{1} pixels")]
+    BadSheetSize(usize, usize),
+    #[error("animation script error on line {0}: {1}")]
+    BadScript(usize, &'static str),
+    #[error("animation script must define mode {0} exactly once")]
+    BadMode(usize),
+    #[error("animation script uses frame {0}, which wasn't provided")]
+    MissingFrame(String),
+    #[error("frame {0} doesn't fit in one 32x8-tile sheet")]
+    FrameTooLarge(String),
+    #[error("tileset width and height must be multiples of 16")]
+    BadTilesetDimensions,
+    #[error("icon sheet width and height must be multiples of 16")]
+    BadIconSheetDimensions,
+    #[error("icon {0} takes the sheet past 16 colors")]
+    IconColors(usize),
+    #[error("icon {0} uses colors past the first 16 of the palette")]
+    IconIndex(usize),
+    #[error("animation has no frames")]
+    NoFrames,
+    #[error("frame {0} isn't the same size as the first")]
+    FrameSizeMismatch(usize),
+    #[error("glyph cells must be a whole number of tiles, not {0}x{1}")]
+    BadGlyphCell(usize, usize),
+    #[error("a {0}x{1} sheet doesn't divide into {2}x{3} glyph cells")]
+    BadGlyphSheet(usize, usize, usize, usize),
+    #[error("can't reduce an image to {0} colors")]
+    TooFewColors(usize),
+    #[error("can't reduce an image to {0} colors; indices stop at 255")]
+    TooManyColorsAsked(usize),
+    #[error("image has too many unique tiles")]
+    TooManyTiles,
+    #[error("image uses palette index {0}, but only {1} colors are allowed")]
+    IndexOutOfRange(usize, usize),
+    #[error("got {0} pixel indices for a {1}x{2} image")]
+    IndexCount(usize, usize, usize),
+    #[error("({0}, {1}) is outside the image")]
+    OutOfBounds(usize, usize),
+    #[error(
+        "palette indices {0} to {1} aren't all within the {2}-color palette"
+    )]
+    BadPaletteRange(usize, usize, usize),
+    #[error("couldn't read palette file: {0}")]
+    BadPaletteFile(&'static str),
 
     // Internal errors/bugs (raised by [validate])
     #[error("BUG: image dimensions don't match internal buffer")]
     DimensionMismatch,
     #[error("BUG: image contains color index >15")]
     BadColorIndex,
+    #[error("BUG: palette block isn't up to 16 whole banks")]
+    BadPaletteBanks,
+    #[error("BUG: tile ({0}, {1}) uses colors outside its palette bank, {2}")]
+    TileOutsideBank(usize, usize, usize),
 
     // Errors from other libraries
     #[error("error processing image")]
     ImageError(#[from] image::ImageError),
     #[error("error processing png image")]
     PngError(#[from] png::DecodingError),
+    #[error("error writing png image")]
+    PngEncodingError(#[from] png::EncodingError),
 }
 
-#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
+impl Error {
+    // What to highlight in the input image, for errors that can say.
+    pub fn diagnosis(&self) -> Option<Diagnosis> {
+        match self {
+            Self::TooManyColors(d) | Self::UnknownColor(d) if !d.is_empty() => {
+                Some(d.clone())
+            }
+            &Self::BadDimensions(width, height) => {
+                Some(Diagnosis::for_dimensions(width, height))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    // 1bpp and 2bpp aren't used by the hardware, but games use them for fonts
+    // and expand them at runtime.
+    One,
+    Two,
+    #[default]
+    Four,
+    Eight,
+}
+
+impl BitDepth {
+    pub fn bits(self) -> usize {
+        match self {
+            Self::One => 1,
+            Self::Two => 2,
+            Self::Four => 4,
+            Self::Eight => 8,
+        }
+    }
+
+    // Size of one 8x8 tile, in bytes.
+    pub fn tile_size(self) -> usize {
+        8 * self.bits()
+    }
+
+    // How many colors a pixel index can address.
+    pub fn colors(self) -> usize {
+        1 << self.bits()
+    }
+}
+
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Ord, PartialOrd, Debug)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -51,6 +175,9 @@ impl Color {
         Self { r, g, b }
     }
 
+    // The low 3 bits of each channel are left at zero, which is what images
+    // painted against GBA palettes use, so colors read back this way match
+    // them exactly. Use [Color::preview] for something to look at.
     pub fn from_16bit(p: u16) -> Self {
         const LOW5_MASK: u16 = 0b11111;
         let r = ((p & LOW5_MASK) as u8) << 3;
@@ -74,6 +201,51 @@ impl Color {
         let short = self.to_16bit();
         [(short & LOW8_MASK) as u8, ((short >> 8) & LOW8_MASK) as u8]
     }
+
+    // The color as it should be shown, going by the 5 bits per channel the
+    // GBA actually keeps.
+    pub fn preview(self, preview: Preview) -> Self {
+        // Repeating the top bits in the low ones takes 31 to 255 rather than
+        // 248, so the full range is used.
+        let expand = |c: u8| (c & 0xF8) | (c >> 5);
+        match preview {
+            Preview::Raw => self,
+            Preview::Expanded => {
+                Self::rgb(expand(self.r), expand(self.g), expand(self.b))
+            }
+            Preview::Lcd => {
+                // The GBA's unlit LCD has a gamma of about 4 and its channels
+                // bleed into each other; this is the model higan uses, mapped
+                // back to a 2.2 gamma display.
+                let [r, g, b] = [self.r, self.g, self.b]
+                    .map(|c| ((c >> 3) as f64 / 31.0).powf(4.0));
+                let out = |mix: f64| {
+                    let v =
+                        (mix / 255.0).powf(1.0 / 2.2) * 255.0 * 255.0 / 280.0;
+                    v.round().clamp(0.0, 255.0) as u8
+                };
+                Self::rgb(
+                    out(50.0 * g + 255.0 * r),
+                    out(30.0 * b + 230.0 * g + 10.0 * r),
+                    out(220.0 * b + 10.0 * g + 50.0 * r),
+                )
+            }
+        }
+    }
+}
+
+// How to show GBA colors on a PC screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Preview {
+    // The stored 8-bit values, unchanged. These round-trip exactly, but come
+    // out slightly dark.
+    #[default]
+    Raw,
+    // Each 5-bit channel stretched to the full 8-bit range.
+    Expanded,
+    // Corrected to look like the GBA's own screen: darker and less
+    // saturated.
+    Lcd,
 }
 
 impl std::fmt::Display for Color {
@@ -86,14 +258,148 @@ impl std::fmt::Display for Color {
 
 impl<P> From<P> for Color
 where
-    P: Pixel<Subpixel = u8>,
+    P: Pixel,
 {
     fn from(p: P) -> Self {
         let Rgb([r, g, b]) = p.to_rgb();
-        Self::rgb(r, g, b)
+        Self::rgb(subpixel_to_u8(r), subpixel_to_u8(g), subpixel_to_u8(b))
+    }
+}
+
+// Integer subpixels are narrowed by dropping their low bits, so e.g. a 16-bit
+// channel keeps its high byte. Floating-point subpixels are scaled from
+// [0, 1].
+fn subpixel_to_u8<S: Primitive>(s: S) -> u8 {
+    let max = S::DEFAULT_MAX_VALUE.to_f64().unwrap_or(255.0);
+    let bits = (max + 1.0).log2();
+
+    if max.fract() == 0.0 && bits.fract() == 0.0 && bits >= 8.0 {
+        let v = s.to_u64().unwrap_or(0);
+        (v >> (bits as u32 - 8)) as u8
+    } else {
+        let v = s.to_f64().unwrap_or(0.0) / max;
+        (v.clamp(0.0, 1.0) * 255.0).round() as u8
     }
 }
 
+fn trim_lsbs<P, Q>(
+    buf: &ImageBuffer<P, Vec<P::Subpixel>>,
+) -> ImageBuffer<Q, Vec<u8>>
+where
+    P: Pixel,
+    Q: Pixel<Subpixel = u8>,
+{
+    let data = buf.as_raw().iter().map(|&s| subpixel_to_u8(s)).collect();
+    // Callers pair up pixel types with the same channel layout.
+    ImageBuffer::from_raw(buf.width(), buf.height(), data).unwrap()
+}
+
+// Narrows any decoded image to 8 bits per channel, keeping its color type.
+// `DynamicImage` can do this itself, but it rounds rather than truncating,
+// which disagrees with how we treat wide images everywhere else.
+pub fn to_8bit(img: DynamicImage) -> DynamicImage {
+    use DynamicImage::*;
+
+    match img {
+        ImageLuma8(_) | ImageLumaA8(_) | ImageRgb8(_) | ImageRgba8(_) => img,
+        ImageLuma16(buf) => ImageLuma8(trim_lsbs(&buf)),
+        ImageLumaA16(buf) => ImageLumaA8(trim_lsbs(&buf)),
+        ImageRgb16(buf) => ImageRgb8(trim_lsbs(&buf)),
+        ImageRgba16(buf) => ImageRgba8(trim_lsbs(&buf)),
+        ImageRgb32F(buf) => ImageRgb8(trim_lsbs(&buf)),
+        ImageRgba32F(buf) => ImageRgba8(trim_lsbs(&buf)),
+        // `DynamicImage` is non-exhaustive; anything new gets the default
+        // treatment.
+        img => ImageRgba8(img.to_rgba8()),
+    }
+}
+
+// How to handle pixels with an alpha channel.
+//
+// Transparent pixels are always given palette index 0, which the GBA doesn't
+// draw for sprites (or for any background layer other than the lowest).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Transparency {
+    // Pixels whose alpha is at or below this are transparent.
+    pub alpha_threshold: u8,
+    // The color to store at index 0. If absent, we use whatever RGB value the
+    // first transparent pixel happens to have.
+    pub backdrop: Option<Color>,
+}
+
+impl Transparency {
+    pub fn is_transparent<P: Pixel>(&self, p: &P) -> bool {
+        let Rgba([_, _, _, a]) = p.to_rgba();
+        subpixel_to_u8(a) <= self.alpha_threshold
+    }
+
+    // The color to put at index 0 for [img], if it needs one.
+    pub fn backdrop_for<V>(&self, img: &V) -> Option<Color>
+    where
+        V: GenericImageView,
+        V::Pixel: Pixel,
+    {
+        self.backdrop.or_else(|| {
+            img.pixels()
+                .find(|(_x, _y, pix)| self.is_transparent(pix))
+                .map(|(_x, _y, pix)| Color::from(pix))
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConvertOptions {
+    // Use these colors, in order, rather than inferring a palette.
+    pub palette: Option<Palette>,
+    pub transparency: Option<Transparency>,
+    // Limits the palette to what tiles of this depth can use.
+    pub depth: BitDepth,
+    // With a fixed palette, move colors that aren't in it onto palette
+    // entries rather than rejecting them.
+    pub remap: Option<Remap>,
+}
+
+// How colors missing from a fixed palette are remapped. A color first matches
+// any entry the GBA would display identically (same [Color::to_16bit]), and
+// failing that the nearest entry with no channel more than [tolerance] 5-bit
+// steps away.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Remap {
+    pub tolerance: u8,
+}
+
+impl Remap {
+    fn find(&self, color: Color, palette: &[Color]) -> Option<usize> {
+        let channels = |c: Color| [c.r >> 3, c.g >> 3, c.b >> 3];
+        let wanted = channels(color);
+
+        palette
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, &c)| {
+                let have = channels(c);
+                let diffs = [0, 1, 2].map(|i| have[i].abs_diff(wanted[i]));
+                let within = diffs.iter().all(|&d| d <= self.tolerance);
+                let dist: u32 =
+                    diffs.iter().map(|&d| d as u32 * d as u32).sum();
+                within.then_some((dist, idx))
+            })
+            .min()
+            .map(|(_dist, idx)| idx)
+    }
+}
+
+// A color [Remap] moved onto a palette entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RemappedColor {
+    pub color: Color,
+    pub index: usize,
+    // Number of pixels that had this color.
+    pub count: usize,
+    // Where the color first appears, as (x, y).
+    pub first: (usize, usize),
+}
+
 #[derive(Clone, Debug)]
 pub struct Palette(Vec<Color>);
 
@@ -157,9 +463,11 @@ pub struct GBAImage {
     pub height: usize,
     // INVARIANT: forall i . data[i] < palette.len()
     // INVARIANT: data.len() = width * height, row-major.
-    data: Vec<usize>,
+    // Indices never need more than 8 bits, so that's all we store.
+    data: Vec<u8>,
 }
 
+#[derive(Clone, Copy)]
 pub struct GBAImageView<'a> {
     owner: &'a GBAImage,
     x: usize,
@@ -170,19 +478,27 @@ pub struct GBAImageView<'a> {
 
 impl GBAImage {
     pub fn validate(&self) -> Result<(), Error> {
+        self.validate_with_depth(BitDepth::Four)
+    }
+
+    pub fn validate_with_depth(&self, depth: BitDepth) -> Result<(), Error> {
         if self.data.len() != self.width * self.height {
             return Err(Error::DimensionMismatch);
         }
 
-        if self.width % 8 != 0 && self.height % 8 != 0 {
-            return Err(Error::BadDimensions);
+        if self.width % 8 != 0 || self.height % 8 != 0 {
+            return Err(Error::BadDimensions(self.width, self.height));
         }
 
-        if let Some(_idx) = self.data.iter().find(|&&idx| idx > 0xF) {
-            return Err(Error::BadColorIndex);
-        }
-
-        Ok(())
+        // An inferred palette can end up with more colors than the depth
+        // allows, which is the artist's problem rather than ours.
+        check_excess_colors(
+            self.width,
+            self.height,
+            |i| self.data[i] as usize,
+            &self.palette,
+            depth.colors(),
+        )
     }
 
     pub fn pixel_at(&self, x: usize, y: usize) -> Option<usize> {
@@ -190,7 +506,7 @@ impl GBAImage {
             return None;
         }
 
-        self.data.get(y * self.width + x).copied()
+        self.data.get(y * self.width + x).map(|&idx| idx as usize)
     }
 
     pub fn color_at(&self, x: usize, y: usize) -> Option<Color> {
@@ -205,16 +521,20 @@ impl GBAImage {
         }
     }
 
+    // Like [GBAImage::tiles], but in the order given by [arrangement].
+    pub fn arranged_tiles<'a>(
+        &'a self,
+        arrangement: &arrange::Arrangement,
+    ) -> Result<impl Iterator<Item = GBAImageView<'a>>, Error> {
+        let order = arrangement
+            .order(self.width.div_ceil(8), self.height.div_ceil(8))?;
+        Ok(order
+            .into_iter()
+            .map(|(x, y)| self.view(x * 8, y * 8, 8, 8)))
+    }
+
     pub fn pixels<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
-        PixelIterator {
-            owner: self,
-            x_offs: 0,
-            y_offs: 0,
-            x: 0,
-            y: 0,
-            width: self.width,
-            height: self.height,
-        }
+        self.data.iter().map(|&idx| idx as usize)
     }
 
     pub fn view(
@@ -239,16 +559,43 @@ impl GBAImage {
     ) -> Result<Self, Error>
     where
         V: GenericImageView,
-        V::Pixel: Pixel<Subpixel = u8>,
+        V::Pixel: Pixel,
+    {
+        Self::convert(
+            img,
+            &ConvertOptions {
+                palette: colors,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn convert<V>(img: &V, opts: &ConvertOptions) -> Result<Self, Error>
+    where
+        V: GenericImageView,
+        V::Pixel: Pixel,
     {
-        let (fixed_palette, mut colors) = match colors {
+        Self::convert_with_report(img, opts).map(|(image, _report)| image)
+    }
+
+    // Like [GBAImage::convert], but also returns every color `opts.remap`
+    // moved, in order of first appearance.
+    pub fn convert_with_report<V>(
+        img: &V,
+        opts: &ConvertOptions,
+    ) -> Result<(Self, Vec<RemappedColor>), Error>
+    where
+        V: GenericImageView,
+        V::Pixel: Pixel,
+    {
+        let (fixed_palette, mut colors) = match &opts.palette {
             None => (false, HashMap::new()),
             Some(colors) => (
                 true,
                 colors
                     .0
                     .iter()
-                    .take(16)
+                    .take(opts.depth.colors())
                     .enumerate()
                     .map(|(x, i)| (*i, x))
                     .collect::<HashMap<Color, usize>>(),
@@ -256,47 +603,127 @@ impl GBAImage {
         };
         let width = img.width() as usize;
         let height = img.height() as usize;
-        let mut count = colors.len();
+
+        // With an inferred palette, the backdrop claims index 0 up front.
+        // It's kept out of [colors], so opaque pixels that happen to share
+        // its color still get an index of their own.
+        let backdrop = match &opts.transparency {
+            Some(transparency) if !fixed_palette => {
+                transparency.backdrop_for(img)
+            }
+            _ => None,
+        };
+
+        let mut count = colors.len() + backdrop.is_some() as usize;
+        let fixed_colors = opts
+            .palette
+            .iter()
+            .flat_map(|p| p.0.iter().copied().take(opts.depth.colors()))
+            .collect::<Vec<_>>();
+        let mut remapped: HashMap<Color, RemappedColor> = HashMap::new();
+        // Gathered rather than failing on the first, so the error can point
+        // at all of them.
+        let mut unknown = Vec::new();
 
         let data = img
             .pixels()
-            .map(|(_x, _y, pix)| {
+            .map(|(x, y, pix)| {
+                if let Some(transparency) = &opts.transparency {
+                    if transparency.is_transparent(&pix) {
+                        return 0;
+                    }
+                }
+
                 let color = Color::from(pix);
 
+                if let Some(remap) = remapped.get_mut(&color) {
+                    remap.count += 1;
+                    return remap.index;
+                }
+
                 match colors.get(&color) {
-                    Some(idx) => Ok(*idx),
+                    Some(idx) => *idx,
                     None => {
                         if fixed_palette {
-                            Err(Error::UnknownColor)
+                            let Some(index) = opts
+                                .remap
+                                .and_then(|r| r.find(color, &fixed_colors))
+                            else {
+                                unknown.push((x as usize, y as usize, color));
+                                return 0;
+                            };
+                            remapped.insert(
+                                color,
+                                RemappedColor {
+                                    color,
+                                    index,
+                                    count: 1,
+                                    first: (x as usize, y as usize),
+                                },
+                            );
+                            index
                         } else {
                             let idx = count;
                             colors.insert(color, idx);
                             count += 1;
-                            Ok(idx)
+                            idx
                         }
                     }
                 }
             })
-            .collect::<Result<Vec<_>, Error>>()?;
+            .collect::<Vec<_>>();
+
+        if !unknown.is_empty() {
+            return Err(Error::UnknownColor(Diagnosis::from_pixels(
+                width,
+                height,
+                unknown,
+                |x, y| Color::from(img.get_pixel(x as u32, y as u32)),
+            )));
+        }
 
-        let palette = colors
+        let palette = backdrop
             .into_iter()
-            .sorted_by(|&(_c1, idx1), &(_c2, idx2)| idx1.cmp(&idx2))
-            .map(|(c, _idx)| c)
+            .chain(
+                colors
+                    .into_iter()
+                    .sorted_by(|&(_c1, idx1), &(_c2, idx2)| idx1.cmp(&idx2))
+                    .map(|(c, _idx)| c),
+            )
             .collect();
 
-        Ok(Self {
-            palette,
-            width,
-            height,
-            data,
-        })
+        // Past 256 colors, indices won't fit in a byte, let alone in a tile.
+        if count > 1 << 8 {
+            check_excess_colors(
+                width,
+                height,
+                |i| data[i],
+                &palette,
+                opts.depth.colors(),
+            )?;
+        }
+        let data = data.into_iter().map(|idx| idx as u8).collect();
+
+        let report = remapped
+            .into_values()
+            .sorted_by_key(|r| (r.first.1, r.first.0))
+            .collect();
+
+        Ok((
+            Self {
+                palette,
+                width,
+                height,
+                data,
+            },
+            report,
+        ))
     }
 
     pub fn with_inferred_palette<V>(img: &V) -> Result<Self, Error>
     where
         V: GenericImageView,
-        V::Pixel: Pixel<Subpixel = u8>,
+        V::Pixel: Pixel,
     {
         Self::from_generic_image(img, None)
     }
@@ -307,13 +734,267 @@ impl GBAImage {
     ) -> Result<Self, Error>
     where
         V: GenericImageView,
-        V::Pixel: Pixel<Subpixel = u8>,
+        V::Pixel: Pixel,
     {
         Self::from_generic_image(img, Some(palette))
     }
+
+    // Reads an indexed PNG, keeping its pixel indices and palette exactly as
+    // they are, duplicate palette entries and all. Returns `None` if the image
+    // isn't indexed.
+    //
+    // The palette is cut down to [max_colors], and it's an error for any
+    // pixel to use an index past that.
+    //
+    // With [transparency], pixels whose index is transparent (going by the
+    // tRNS chunk) move to index 0, and index 0 takes the backdrop color, as
+    // in [GBAImage::convert_with_report]. Without it, tRNS is ignored.
+    pub fn from_indexed_png(
+        buf: &[u8],
+        max_colors: usize,
+        transparency: Option<&Transparency>,
+    ) -> Result<Option<Self>, Error> {
+        let mut decoder = png::Decoder::new(buf);
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info()?;
+
+        if reader.info().color_type != png::ColorType::Indexed {
+            return Ok(None);
+        }
+
+        let mut palette = match reader.info().palette.as_ref() {
+            Some(palette_bytes) => palette_bytes
+                .iter()
+                .tuples::<(_, _, _)>()
+                .map(|(r, g, b)| Color::rgb(*r, *g, *b))
+                .take(max_colors)
+                .collect::<Palette>(),
+            None => return Ok(None),
+        };
+        let alphas = reader.info().trns.as_deref().unwrap_or(&[]).to_vec();
+
+        let mut raw = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut raw)?;
+        let bits = frame.bit_depth as usize;
+        let mask = ((1u16 << bits) - 1) as u8;
+        let width = frame.width as usize;
+        let height = frame.height as usize;
+
+        let mut data = raw
+            .chunks(frame.line_size)
+            .take(height)
+            .flat_map(|row| {
+                (0..width).map(move |x| {
+                    let offset = x * bits;
+                    let shift = 8 - bits - offset % 8;
+                    ((row[offset / 8] >> shift) & mask) as usize
+                })
+            })
+            .map(|idx| {
+                if idx < palette.len() {
+                    Ok(idx as u8)
+                } else {
+                    Err(Error::IndexOutOfRange(idx, palette.len()))
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some(transparency) = transparency {
+            // Indices past the end of tRNS are opaque.
+            let is_transparent = |idx: u8| {
+                let alpha = alphas.get(idx as usize).copied().unwrap_or(0xFF);
+                alpha <= transparency.alpha_threshold
+            };
+            let first = data.iter().copied().find(|&idx| is_transparent(idx));
+            let backdrop = transparency
+                .backdrop
+                .or_else(|| first.and_then(|idx| palette.lookup(idx as usize)));
+
+            for idx in data.iter_mut().filter(|idx| is_transparent(**idx)) {
+                *idx = 0;
+            }
+            if let (Some(backdrop), Some(entry)) =
+                (backdrop, palette.0.first_mut())
+            {
+                *entry = backdrop;
+            }
+        }
+
+        Ok(Some(Self {
+            palette,
+            width,
+            height,
+            data,
+        }))
+    }
+
+    // Rebuilds an image from 4bpp tile data, laid out [width] tiles across.
+    pub fn from_tiles(
+        bytes: &[u8],
+        palette: Palette,
+        width: usize,
+    ) -> Result<Self, Error> {
+        Self::from_tiles_with_depth(bytes, palette, width, BitDepth::Four)
+    }
+
+    // Rebuilds an image from raw tile data, laid out [width] tiles across. If
+    // the last row of tiles is incomplete, it is padded out with index 0, as
+    // is a last tile cut short.
+    //
+    // Fails with [Error::IndexOutOfRange] if any pixel's index doesn't fit in
+    // [palette].
+    pub fn from_tiles_with_depth(
+        bytes: &[u8],
+        palette: Palette,
+        width: usize,
+        depth: BitDepth,
+    ) -> Result<Self, Error> {
+        let tile_count = bytes.len().div_ceil(depth.tile_size());
+        let mut padded;
+        let bytes = if bytes.len().is_multiple_of(depth.tile_size()) {
+            bytes
+        } else {
+            padded = bytes.to_vec();
+            padded.resize(tile_count * depth.tile_size(), 0);
+            &padded[..]
+        };
+        let width = width.max(1);
+        let tiles_high = tile_count.div_ceil(width);
+
+        let mut image = Self {
+            palette,
+            width: width * 8,
+            height: tiles_high * 8,
+            data: vec![0; width * 8 * tiles_high * 8],
+        };
+
+        // Each row of a tile is a contiguous run of bytes in the input, and
+        // lands in a contiguous run of pixels in the image.
+        let row_size = depth.tile_size() / 8;
+        for (i, tile) in bytes.chunks_exact(depth.tile_size()).enumerate() {
+            let tile_x = (i % width) * 8;
+            let tile_y = (i / width) * 8;
+            for (y, row) in tile.chunks_exact(row_size).enumerate() {
+                let start = (tile_y + y) * image.width + tile_x;
+                let dest = &mut image.data[start..start + 8];
+                match depth {
+                    BitDepth::Four => {
+                        for (pair, &b) in dest.chunks_exact_mut(2).zip(row) {
+                            pair[0] = b & 0xF;
+                            pair[1] = b >> 4;
+                        }
+                    }
+                    BitDepth::Eight => dest.copy_from_slice(row),
+                    BitDepth::One | BitDepth::Two => {
+                        let bits = depth.bits();
+                        let mask = (1 << bits) - 1;
+                        for (x, idx) in dest.iter_mut().enumerate() {
+                            let bit = x * bits;
+                            *idx = (row[bit / 8] >> (bit % 8)) & mask;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(&idx) = image
+            .data
+            .iter()
+            .find(|&&idx| idx as usize >= image.palette.len())
+        {
+            return Err(Error::IndexOutOfRange(
+                idx as usize,
+                image.palette.len(),
+            ));
+        }
+
+        Ok(image)
+    }
+
+    // Encodes the image as an indexed PNG whose palette is exactly
+    // `self.palette`, in order.
+    pub fn encode_png(&self) -> Result<Vec<u8>, Error> {
+        self.encode_png_with_preview(Preview::Raw)
+    }
+
+    // Like [GBAImage::encode_png], but with the palette's colors adjusted
+    // for viewing. The indices are unchanged.
+    pub fn encode_png_with_preview(
+        &self,
+        preview: Preview,
+    ) -> Result<Vec<u8>, Error> {
+        // A PNG's palette can't have more entries than its depth can index,
+        // so we only drop to 4 bits if both the palette and every index fit.
+        let largest = self.data.iter().copied().max().unwrap_or(0);
+        let depth = if self.palette.len() <= 16 && largest < 16 {
+            png::BitDepth::Four
+        } else {
+            png::BitDepth::Eight
+        };
+
+        let mut palette = self
+            .palette
+            .0
+            .iter()
+            .take(256)
+            .map(|c| c.preview(preview))
+            .flat_map(|c| [c.r, c.g, c.b])
+            .collect_vec();
+        // PNG palettes can't be empty.
+        if palette.is_empty() {
+            palette = vec![0, 0, 0];
+        }
+
+        let rows = self
+            .data
+            .chunks(self.width.max(1))
+            .flat_map(|row| match depth {
+                png::BitDepth::Four => row
+                    .chunks(2)
+                    .map(|pair| {
+                        pair[0] << 4 | pair.get(1).copied().unwrap_or(0)
+                    })
+                    .collect_vec(),
+                _ => row.to_vec(),
+            })
+            .collect_vec();
+
+        let mut buf = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(
+                &mut buf,
+                self.width as u32,
+                self.height as u32,
+            );
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(depth);
+            encoder.set_palette(palette);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(&rows[..])?;
+        }
+
+        Ok(buf)
+    }
 }
 
 impl<'owner> GBAImageView<'owner> {
+    // A view of part of this view. Coordinates are relative to this view.
+    pub fn view(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> GBAImageView<'owner> {
+        GBAImageView {
+            owner: self.owner,
+            x: self.x + x,
+            y: self.y + y,
+            width,
+            height,
+        }
+    }
+
     pub fn pixel_at(&self, x: usize, y: usize) -> Option<usize> {
         if x >= self.width || y >= self.height {
             return None;
@@ -330,15 +1011,39 @@ impl<'owner> GBAImageView<'owner> {
     }
 
     pub fn pixels<'a>(&'a self) -> impl Iterator<Item = usize> + 'owner {
-        PixelIterator {
-            owner: self.owner,
-            x_offs: self.x,
-            y_offs: self.y,
-            x: 0,
-            y: 0,
-            width: self.width,
-            height: self.height,
+        self.rows().flatten().map(|&idx| idx as usize)
+    }
+
+    // The view's pixel indices, as stored.
+    pub(crate) fn indices(&self) -> impl Iterator<Item = u8> + 'owner {
+        self.rows().flatten().copied()
+    }
+
+    // Row [y] of the view, straight out of the owner's data. Cut short at the
+    // owner's right edge, and empty past its bottom edge.
+    fn row(&self, y: usize) -> &'owner [u8] {
+        let owner = self.owner;
+        let (x, y) = (self.x, self.y + y);
+        if y >= owner.height || x >= owner.width {
+            return &[];
         }
+
+        let start = y * owner.width + x;
+        &owner.data[start..start + self.width.min(owner.width - x)]
+    }
+
+    // The view's rows, in order. Like [GBAImageView::pixel_at], these stop
+    // at the first pixel that's past the edge of the owner.
+    fn rows(&self) -> impl Iterator<Item = &'owner [u8]> {
+        let view = *self;
+        (0..self.height).map(move |y| view.row(y)).scan(
+            true,
+            move |whole, row| {
+                let last_whole = *whole;
+                *whole = row.len() == view.width;
+                last_whole.then_some(row)
+            },
+        )
     }
 }
 
@@ -348,16 +1053,6 @@ struct Tiles<'a> {
     y: usize,
 }
 
-struct PixelIterator<'a> {
-    owner: &'a GBAImage,
-    x_offs: usize,
-    y_offs: usize,
-    x: usize,
-    y: usize,
-    width: usize,
-    height: usize,
-}
-
 impl<'owner> Iterator for Tiles<'owner> {
     type Item = GBAImageView<'owner>;
 
@@ -380,26 +1075,37 @@ impl<'owner> Iterator for Tiles<'owner> {
     }
 }
 
-impl<'owner> Iterator for PixelIterator<'owner> {
-    type Item = usize;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let true_x = self.x + self.x_offs;
-        let true_y = self.y + self.y_offs;
-
-        if self.y >= self.height {
-            return None;
-        }
-
-        self.x += 1;
-
-        if self.x >= self.width {
-            self.x = 0;
-            self.y += 1;
-        }
-
-        self.owner.pixel_at(true_x, true_y)
+// Fails with [Error::TooManyColors] if any pixel uses index [limit] or above,
+// pointing at every color past the limit. Those only come last by scan order,
+// so the diagnosis also counts every tile's colors, to show where there are
+// colors to spare. [index_at] gives the index of each pixel, row-major.
+fn check_excess_colors(
+    width: usize,
+    height: usize,
+    index_at: impl Fn(usize) -> usize,
+    palette: &Palette,
+    limit: usize,
+) -> Result<(), Error> {
+    let excess = (0..width * height)
+        .filter(|&i| index_at(i) >= limit)
+        .map(|i| {
+            let color =
+                palette.lookup(index_at(i)).ok_or(Error::BadColorIndex)?;
+            Ok((i % width, i / width, color))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if excess.is_empty() {
+        return Ok(());
     }
+
+    let color_at = |x: usize, y: usize| {
+        palette
+            .lookup(index_at(y * width + x))
+            .unwrap_or(Color::rgb(0, 0, 0))
+    };
+    Err(Error::TooManyColors(Diagnosis::with_every_tile(
+        width, height, excess, color_at,
+    )))
 }
 
 pub fn parse_palette_string(s: impl AsRef<str>) -> Option<Palette> {
@@ -428,7 +1134,7 @@ pub fn parse_palette_string(s: impl AsRef<str>) -> Option<Palette> {
 pub fn read_colors_from_image<V>(img: &V) -> Palette
 where
     V: GenericImageView,
-    V::Pixel: Pixel<Subpixel = u8>,
+    V::Pixel: Pixel,
 {
     img.pixels()
         .take(16)
@@ -440,7 +1146,7 @@ where
 fn guess_fixed_palette<V>(img: &V) -> Option<Palette>
 where
     V: GenericImageView,
-    V::Pixel: Pixel<Subpixel = u8>,
+    V::Pixel: Pixel,
 {
     // If there are exactly 16 unique colors in the top left, use that as the
     // palette, in that order.
@@ -494,15 +1200,72 @@ fn load_png_palette(buf: &[u8]) -> Result<Option<Palette>, Error> {
     Ok(None)
 }
 
+pub(crate) fn resolve_format(
+    buf: &[u8],
+    format: Option<ImageFormat>,
+) -> Result<ImageFormat, Error> {
+    match format {
+        Some(format) => Ok(format),
+        None => Ok(guess_format(buf)?),
+    }
+}
+
+pub fn decode_image(
+    buf: &[u8],
+    format: Option<ImageFormat>,
+) -> Result<DynamicImage, Error> {
+    let mut reader = ImageReader::new(Cursor::new(buf));
+    reader.set_format(resolve_format(buf, format)?);
+    Ok(to_8bit(reader.decode()?))
+}
+
 pub fn convert_image(
     buf: &[u8],
     format: Option<ImageFormat>,
     palette: Option<Palette>,
 ) -> Result<GBAImage, Error> {
-    let format = match format {
-        Some(format) => format,
-        None => guess_format(buf)?,
-    };
+    convert_image_with_options(
+        buf,
+        format,
+        ConvertOptions {
+            palette,
+            ..Default::default()
+        },
+    )
+}
+
+// Like [convert_image], but if `opts.palette` is absent, we try to find one in
+// the image itself before falling back to inferring it.
+pub fn convert_image_with_options(
+    buf: &[u8],
+    format: Option<ImageFormat>,
+    opts: ConvertOptions,
+) -> Result<GBAImage, Error> {
+    convert_image_with_report(buf, format, opts).map(|(image, _report)| image)
+}
+
+// Like [convert_image_with_options], but also returns the colors `opts.remap`
+// moved; see [GBAImage::convert_with_report].
+pub fn convert_image_with_report(
+    buf: &[u8],
+    format: Option<ImageFormat>,
+    opts: ConvertOptions,
+) -> Result<(GBAImage, Vec<RemappedColor>), Error> {
+    let format = resolve_format(buf, format)?;
+
+    // Indexed PNGs already say which index each pixel should use, so we take
+    // them at their word rather than matching colors.
+    if opts.palette.is_none() && format == ImageFormat::Png {
+        if let Some(image) = GBAImage::from_indexed_png(
+            buf,
+            opts.depth.colors(),
+            opts.transparency.as_ref(),
+        )? {
+            return Ok((image, Vec::new()));
+        }
+    }
+
+    let palette = opts.palette;
 
     let palette = if matches!(palette, None) {
         use ImageFormat::*;
@@ -514,9 +1277,7 @@ pub fn convert_image(
         palette
     };
 
-    let mut reader = ImageReader::new(Cursor::new(buf));
-    reader.set_format(format);
-    let img = reader.decode()?;
+    let img = decode_image(buf, Some(format))?;
 
     let palette = if matches!(palette, None) {
         guess_fixed_palette(&img)
@@ -524,16 +1285,69 @@ pub fn convert_image(
         palette
     };
 
-    GBAImage::from_generic_image(&img, palette)
+    GBAImage::convert_with_report(&img, &ConvertOptions { palette, ..opts })
 }
 
-// TODO: do this as an iterator
 pub fn encode_tiles<'img>(
     tiles: impl Iterator<Item = GBAImageView<'img>>,
 ) -> Vec<u8> {
-    tiles
-        .flat_map(|tile| tile.pixels())
-        .tuples::<(_, _)>()
-        .map(|(a, b)| ((a & 0xF) | ((b & 0xF) << 4)) as u8)
+    let pack = |a: u8, b: u8| (a & 0xF) | ((b & 0xF) << 4);
+
+    let mut out = Vec::with_capacity(tiles.size_hint().0 * 32);
+    // Pixels pair up across rows (and views), so a row with an odd number of
+    // them leaves one waiting for the next.
+    let mut pending = None;
+    for tile in tiles {
+        for mut row in tile.rows() {
+            if let Some(a) = pending.take() {
+                match row.split_first() {
+                    Some((&b, rest)) => {
+                        out.push(pack(a, b));
+                        row = rest;
+                    }
+                    None => {
+                        pending = Some(a);
+                        continue;
+                    }
+                }
+            }
+
+            let pairs = row.chunks_exact(2);
+            pending = pairs.remainder().first().copied();
+            out.extend(pairs.map(|pair| pack(pair[0], pair[1])));
+        }
+    }
+    out
+}
+
+pub fn encode_tiles_with_depth<'img>(
+    tiles: impl Iterator<Item = GBAImageView<'img>>,
+    depth: BitDepth,
+) -> Vec<u8> {
+    match depth {
+        BitDepth::Four => encode_tiles(tiles),
+        BitDepth::Eight => tiles.flat_map(|tile| tile.indices()).collect(),
+        BitDepth::One | BitDepth::Two => {
+            pack_indices(tiles.flat_map(|tile| tile.indices()), depth)
+        }
+    }
+}
+
+// Packs pixel indices at [depth], leftmost pixel in the lowest bits, as the
+// GBA does. A final partial byte is padded with index 0.
+pub(crate) fn pack_indices(
+    indices: impl Iterator<Item = u8>,
+    depth: BitDepth,
+) -> Vec<u8> {
+    let bits = depth.bits();
+    let mask = ((1u16 << bits) - 1) as u8;
+    indices
+        .chunks(8 / bits)
+        .into_iter()
+        .map(|chunk| {
+            chunk
+                .enumerate()
+                .fold(0, |byte, (i, idx)| byte | (idx & mask) << (i * bits))
+        })
         .collect()
 }
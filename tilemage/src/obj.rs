@@ -0,0 +1,355 @@
+// OBJ (sprite) support.
+//
+// The GBA can only draw sprites in a handful of fixed sizes, so anything
+// bigger or oddly shaped has to be assembled out of several OAM entries. We
+// cover the visible (non-zero) pixels of an image with legal shapes, then lay
+// out each piece's tiles the way VRAM expects them.
+
+use crate::{BitDepth, Error, GBAImage};
+
+// Sprites (like everything else) count tiles in 32-byte units, and 2D mapping
+// lays VRAM out as a 32-tile-wide grid.
+const VRAM_WIDTH: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mapping {
+    // Each piece's tiles are stored one after another.
+    OneD,
+    // Each piece is a rectangle in a 32-tile-wide sheet.
+    TwoD,
+}
+
+// A legal OAM sprite size. Dimensions are in tiles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ObjShape {
+    pub width: usize,
+    pub height: usize,
+}
+
+// Every legal shape, from most tiles to fewest. [best_shape] breaks ties in
+// favor of later entries, so the 2x2 square goes after the 4x1 and 1x4 strips.
+pub const SHAPES: [ObjShape; 12] = [
+    ObjShape::new(8, 8),
+    ObjShape::new(8, 4),
+    ObjShape::new(4, 8),
+    ObjShape::new(4, 4),
+    ObjShape::new(4, 2),
+    ObjShape::new(2, 4),
+    ObjShape::new(4, 1),
+    ObjShape::new(1, 4),
+    ObjShape::new(2, 2),
+    ObjShape::new(2, 1),
+    ObjShape::new(1, 2),
+    ObjShape::new(1, 1),
+];
+
+impl ObjShape {
+    pub const fn new(width: usize, height: usize) -> Self {
+        Self { width, height }
+    }
+
+    // The (shape, size) fields of attributes 0 and 1, respectively.
+    pub fn attr_bits(self) -> Option<(u16, u16)> {
+        let bits = match (self.width, self.height) {
+            (1, 1) => (0, 0),
+            (2, 2) => (0, 1),
+            (4, 4) => (0, 2),
+            (8, 8) => (0, 3),
+            (2, 1) => (1, 0),
+            (4, 1) => (1, 1),
+            (4, 2) => (1, 2),
+            (8, 4) => (1, 3),
+            (1, 2) => (2, 0),
+            (1, 4) => (2, 1),
+            (2, 4) => (2, 2),
+            (4, 8) => (2, 3),
+            _ => return None,
+        };
+        Some(bits)
+    }
+
+    pub fn tiles(self) -> usize {
+        self.width * self.height
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ObjPiece {
+    pub shape: ObjShape,
+    // Position of the piece relative to the sprite's origin, in pixels.
+    pub x: i32,
+    pub y: i32,
+    // Index of the piece's first tile.
+    pub tile: usize,
+    // Top-left corner of the piece in the source image, in pixels.
+    src_x: usize,
+    src_y: usize,
+}
+
+impl ObjPiece {
+    // The three OAM attributes for this piece (4bpp, no affine or flips).
+    pub fn attributes(&self, palette: usize) -> [u16; 3] {
+        // Every shape in `SHAPES` is legal.
+        let (shape, size) = self.shape.attr_bits().unwrap();
+        [
+            ((self.y as u16) & 0xFF) | (shape << 14),
+            ((self.x as u16) & 0x1FF) | (size << 14),
+            ((self.tile as u16) & 0x3FF) | (((palette & 0xF) as u16) << 12),
+        ]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpriteOptions {
+    pub mapping: Mapping,
+    // Where (0, 0) is for the purposes of piece offsets, in image pixels.
+    pub origin: (i32, i32),
+    // Palette bank written to each piece's OAM entry.
+    pub palette: usize,
+}
+
+impl Default for SpriteOptions {
+    fn default() -> Self {
+        Self {
+            mapping: Mapping::OneD,
+            origin: (0, 0),
+            palette: 0,
+        }
+    }
+}
+
+pub struct Sprite {
+    pub pieces: Vec<ObjPiece>,
+    pub mapping: Mapping,
+    pub palette: usize,
+    // The sprite's tiles as they should appear in VRAM. For 1D mapping, this
+    // is a single 8px-wide column; for 2D, it is 256px wide.
+    pub sheet: GBAImage,
+}
+
+impl Sprite {
+    pub fn encode_tiles(&self) -> Vec<u8> {
+        crate::encode_tiles(self.sheet.tiles())
+    }
+
+    // A halfword piece count, followed by the attributes of each piece.
+    pub fn encode_oam(&self) -> Vec<u8> {
+        let mut result = (self.pieces.len() as u16).to_le_bytes().to_vec();
+        for piece in self.pieces.iter() {
+            for attr in piece.attributes(self.palette) {
+                result.extend(attr.to_le_bytes());
+            }
+        }
+        result
+    }
+
+    pub fn from_image(
+        image: &GBAImage,
+        opts: &SpriteOptions,
+    ) -> Result<Self, Error> {
+        // Sprites can hang off a tile's edge, so this is
+        // [GBAImage::validate] minus the dimension check. Any more than 16
+        // colors and the tiles would come out garbled.
+        crate::check_excess_colors(
+            image.width,
+            image.height,
+            |i| image.data[i] as usize,
+            &image.palette,
+            BitDepth::Four.colors(),
+        )?;
+
+        let tiles_wide = image.width.div_ceil(8);
+        let tiles_high = image.height.div_ceil(8);
+
+        // Which 8x8 cells have anything visible in them. Cells on the right
+        // and bottom edges can hang off the image, so we go pixel by pixel.
+        let opaque = (0..tiles_high)
+            .flat_map(|ty| (0..tiles_wide).map(move |tx| (tx, ty)))
+            .map(|(tx, ty)| {
+                let cell = image.view(tx * 8, ty * 8, 8, 8);
+                (0..64).any(|i| {
+                    cell.pixel_at(i % 8, i / 8).is_some_and(|idx| idx != 0)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let is_opaque = |tx: usize, ty: usize| {
+            tx < tiles_wide && ty < tiles_high && opaque[ty * tiles_wide + tx]
+        };
+
+        let mut covered = vec![false; opaque.len()];
+        let mut pieces = Vec::new();
+
+        for ty in 0..tiles_high {
+            for tx in 0..tiles_wide {
+                if !is_opaque(tx, ty) || covered[ty * tiles_wide + tx] {
+                    continue;
+                }
+
+                let shape = best_shape(tx, ty, is_opaque, |x, y| {
+                    x < tiles_wide
+                        && y < tiles_high
+                        && covered[y * tiles_wide + x]
+                });
+
+                for y in ty..ty + shape.height {
+                    for x in tx..tx + shape.width {
+                        if x < tiles_wide && y < tiles_high {
+                            covered[y * tiles_wide + x] = true;
+                        }
+                    }
+                }
+
+                pieces.push(ObjPiece {
+                    shape,
+                    x: (tx * 8) as i32 - opts.origin.0,
+                    y: (ty * 8) as i32 - opts.origin.1,
+                    tile: 0,
+                    src_x: tx * 8,
+                    src_y: ty * 8,
+                });
+            }
+        }
+
+        let sheet = match opts.mapping {
+            Mapping::OneD => layout_1d(image, &mut pieces),
+            Mapping::TwoD => layout_2d(image, &mut pieces),
+        };
+
+        if sheet.height / 8 * (sheet.width / 8) > crate::bg::MAX_TILES {
+            return Err(Error::TooManyTiles);
+        }
+
+        Ok(Self {
+            pieces,
+            mapping: opts.mapping,
+            palette: opts.palette,
+            sheet,
+        })
+    }
+}
+
+// Picks the largest shape that, placed with its top-left corner at (tx, ty),
+// covers mostly visible cells and doesn't overlap any existing piece. A 1x1
+// piece always qualifies, since we only get here for visible cells.
+fn best_shape(
+    tx: usize,
+    ty: usize,
+    is_opaque: impl Fn(usize, usize) -> bool,
+    is_covered: impl Fn(usize, usize) -> bool,
+) -> ObjShape {
+    SHAPES
+        .iter()
+        .copied()
+        .filter_map(|shape| {
+            let cells = (ty..ty + shape.height)
+                .flat_map(|y| (tx..tx + shape.width).map(move |x| (x, y)))
+                .collect::<Vec<_>>();
+
+            if cells.iter().any(|&(x, y)| is_covered(x, y)) {
+                return None;
+            }
+
+            let visible =
+                cells.iter().filter(|&&(x, y)| is_opaque(x, y)).count();
+
+            // Allow up to a quarter of the piece to be empty, to avoid
+            // splitting sprites into lots of little slivers.
+            if 4 * visible < 3 * shape.tiles() {
+                return None;
+            }
+
+            Some((shape, visible))
+        })
+        .max_by_key(|&(shape, visible)| {
+            (visible, std::cmp::Reverse(shape.tiles()))
+        })
+        .map(|(shape, _)| shape)
+        .unwrap_or(ObjShape::new(1, 1))
+}
+
+// Copies one tile from [image], padding with index 0 outside of its bounds.
+fn copy_tile(
+    image: &GBAImage,
+    x: usize,
+    y: usize,
+) -> impl Iterator<Item = u8> + '_ {
+    let view = image.view(x, y, 8, 8);
+    (0..64).map(move |i| view.pixel_at(i % 8, i / 8).unwrap_or(0) as u8)
+}
+
+fn layout_1d(image: &GBAImage, pieces: &mut [ObjPiece]) -> GBAImage {
+    let mut data = Vec::new();
+    let mut next = 0;
+
+    for piece in pieces.iter_mut() {
+        piece.tile = next;
+        next += piece.shape.tiles();
+
+        for y in 0..piece.shape.height {
+            for x in 0..piece.shape.width {
+                data.extend(copy_tile(
+                    image,
+                    piece.src_x + x * 8,
+                    piece.src_y + y * 8,
+                ));
+            }
+        }
+    }
+
+    GBAImage {
+        palette: image.palette.clone(),
+        width: 8,
+        height: 8 * next,
+        data,
+    }
+}
+
+// Packs the pieces into a 32-tile-wide sheet, tallest first, in rows
+// ("shelves") as tall as the first piece placed in them.
+fn layout_2d(image: &GBAImage, pieces: &mut [ObjPiece]) -> GBAImage {
+    let mut order = (0..pieces.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| std::cmp::Reverse(pieces[i].shape.height));
+
+    let (mut shelf_y, mut shelf_height, mut cursor) = (0, 0, 0);
+    let mut positions = vec![(0, 0); pieces.len()];
+
+    for i in order {
+        let shape = pieces[i].shape;
+        if cursor + shape.width > VRAM_WIDTH {
+            shelf_y += shelf_height;
+            shelf_height = 0;
+            cursor = 0;
+        }
+
+        positions[i] = (cursor, shelf_y);
+        pieces[i].tile = shelf_y * VRAM_WIDTH + cursor;
+        cursor += shape.width;
+        shelf_height = shelf_height.max(shape.height);
+    }
+
+    let width = VRAM_WIDTH * 8;
+    let height = (shelf_y + shelf_height) * 8;
+    let mut data = vec![0; width * height];
+
+    for (piece, &(px, py)) in pieces.iter().zip(positions.iter()) {
+        for y in 0..piece.shape.height {
+            for x in 0..piece.shape.width {
+                let tile =
+                    copy_tile(image, piece.src_x + x * 8, piece.src_y + y * 8);
+                for (i, idx) in tile.enumerate() {
+                    let dx = (px + x) * 8 + i % 8;
+                    let dy = (py + y) * 8 + i / 8;
+                    data[dy * width + dx] = idx;
+                }
+            }
+        }
+    }
+
+    GBAImage {
+        palette: image.palette.clone(),
+        width,
+        height,
+        data,
+    }
+}
@@ -0,0 +1,82 @@
+// Palette animation: series of palettes to swap in one after another, for
+// fades, tints and color cycling.
+//
+// Fades are worked out on the GBA's 5-bit channels rather than on the 8-bit
+// colors we store, so that every step is a color the hardware can show and
+// no two frames look the same unless they have to.
+
+use std::ops::Range;
+
+use crate::{Color, Error, Palette};
+
+impl Palette {
+    // [frames] palettes fading every color from this palette towards
+    // [target], ending [percent]% of the way there (100 for a full fade, less
+    // for a tint). The first frame is this palette, unchanged.
+    pub fn fade(
+        &self,
+        target: Color,
+        frames: usize,
+        percent: u32,
+    ) -> Vec<Self> {
+        let steps = frames.saturating_sub(1).max(1) as i32 * 100;
+        (0..frames)
+            .map(|frame| {
+                let amount = frame as i32 * percent.min(100) as i32;
+                self.0
+                    .iter()
+                    .map(|&color| {
+                        let [r, g, b] = [
+                            (color.r, target.r),
+                            (color.g, target.g),
+                            (color.b, target.b),
+                        ]
+                        .map(|(from, to)| lerp5(from, to, amount, steps));
+                        Color::rgb(r, g, b)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // [frames] palettes rotating the colors in [range] one place further each
+    // frame (towards higher indices, or lower with [reverse]), for water,
+    // lava and the like. The first frame is this palette, unchanged.
+    pub fn cycle(
+        &self,
+        range: Range<usize>,
+        frames: usize,
+        reverse: bool,
+    ) -> Result<Vec<Self>, Error> {
+        if range.is_empty() || range.end > self.len() {
+            return Err(Error::BadPaletteRange(
+                range.start,
+                range.end.saturating_sub(1),
+                self.len(),
+            ));
+        }
+
+        let len = range.len();
+        Ok((0..frames)
+            .map(|frame| {
+                let mut colors = self.0.clone();
+                let cycled = &mut colors[range.clone()];
+                if reverse {
+                    cycled.rotate_left(frame % len);
+                } else {
+                    cycled.rotate_right(frame % len);
+                }
+                Self(colors)
+            })
+            .collect())
+    }
+}
+
+// The 5-bit channel [amount]/[steps] of the way from [from] to [to], rounded
+// to the nearest.
+fn lerp5(from: u8, to: u8, amount: i32, steps: i32) -> u8 {
+    let (from, to) = ((from >> 3) as i32, (to >> 3) as i32);
+    let delta = (to - from) * amount;
+    let rounded = (2 * delta + delta.signum() * steps) / (2 * steps);
+    ((from + rounded) as u8) << 3
+}
@@ -0,0 +1,206 @@
+// Reading and writing palettes in formats other tools understand.
+//
+// Every format here stores 8-bit channels, so colors are written exactly as
+// they appear in the `Palette` (and will lose their low 3 bits once they make
+// it to the GBA).
+
+use std::path::Path;
+
+use crate::{parse_palette_string, Color, Error, Palette};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PaletteFormat {
+    // GBA-native 15-bit colors, two bytes each, little-endian.
+    Raw,
+    // The 64-character hex strings accepted by `parse_palette_string`, one
+    // per line for each 16 colors.
+    Hex,
+    // Paint Shop Pro palette (`.pal`).
+    JascPal,
+    // GIMP palette (`.gpl`).
+    Gimp,
+    // Adobe color table (`.act`).
+    Act,
+}
+
+impl PaletteFormat {
+    // Guesses a format from a file extension. `.pal` is claimed both by
+    // JASC-PAL and by raw GBA palettes, so we assume raw here; use
+    // [PaletteFormat::sniff] when the contents are available.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_lowercase();
+
+        match ext.as_str() {
+            "dmp" | "bin" | "pal" => Some(Self::Raw),
+            "gpl" => Some(Self::Gimp),
+            "act" => Some(Self::Act),
+            "txt" | "hex" => Some(Self::Hex),
+            _ => None,
+        }
+    }
+
+    // Like [PaletteFormat::from_path], but also looks at the file contents to
+    // tell text formats apart from binary ones.
+    pub fn sniff(path: impl AsRef<Path>, data: &[u8]) -> Option<Self> {
+        if data.starts_with(b"JASC-PAL") {
+            return Some(Self::JascPal);
+        }
+        if data.starts_with(b"GIMP Palette") {
+            return Some(Self::Gimp);
+        }
+
+        Self::from_path(path)
+    }
+}
+
+impl Palette {
+    pub fn read(data: &[u8], format: PaletteFormat) -> Result<Self, Error> {
+        match format {
+            PaletteFormat::Raw => Ok(data.iter().copied().collect()),
+            PaletteFormat::Hex => {
+                let lines = text_lines(data)?
+                    .filter(|l| !l.is_empty())
+                    .map(|l| {
+                        parse_palette_string(l).ok_or(Error::BadPaletteFile(
+                            "expected 64 hex digits per line",
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                Ok(lines.into_iter().flat_map(|p| p.0).collect())
+            }
+            PaletteFormat::JascPal => read_jasc(data),
+            PaletteFormat::Gimp => read_gimp(data),
+            PaletteFormat::Act => read_act(data),
+        }
+    }
+
+    pub fn write(&self, format: PaletteFormat) -> Vec<u8> {
+        match format {
+            PaletteFormat::Raw => self.encode(),
+            // Each line has to be exactly 16 colors, so the last one is
+            // padded out with black.
+            PaletteFormat::Hex => self
+                .0
+                .chunks(16)
+                .map(|colors| {
+                    let padding = std::iter::repeat_n(
+                        Color::rgb(0, 0, 0),
+                        16 - colors.len(),
+                    );
+                    let line = colors.iter().copied().chain(padding);
+                    format!("{}\n", line.collect::<Palette>())
+                })
+                .collect::<String>()
+                .into_bytes(),
+            PaletteFormat::JascPal => {
+                let mut out = format!("JASC-PAL\r\n0100\r\n{}\r\n", self.len());
+                for c in self.0.iter() {
+                    out.push_str(&format!("{} {} {}\r\n", c.r, c.g, c.b));
+                }
+                out.into_bytes()
+            }
+            PaletteFormat::Gimp => {
+                let mut out = "GIMP Palette\nName: tilemage\nColumns: 16\n#\n"
+                    .to_string();
+                for (i, c) in self.0.iter().enumerate() {
+                    out.push_str(&format!(
+                        "{:3} {:3} {:3}\tIndex {}\n",
+                        c.r, c.g, c.b, i
+                    ));
+                }
+                out.into_bytes()
+            }
+            PaletteFormat::Act => {
+                let count = self.len().min(256);
+                let mut out = self
+                    .0
+                    .iter()
+                    .take(count)
+                    .flat_map(|c| [c.r, c.g, c.b])
+                    .collect::<Vec<_>>();
+                out.resize(768, 0);
+                out.extend((count as u16).to_be_bytes());
+                // No transparent index.
+                out.extend(0xFFFFu16.to_be_bytes());
+                out
+            }
+        }
+    }
+}
+
+fn text_lines(data: &[u8]) -> Result<impl Iterator<Item = &str>, Error> {
+    let s = std::str::from_utf8(data)
+        .map_err(|_| Error::BadPaletteFile("not valid text"))?;
+    Ok(s.lines().map(str::trim))
+}
+
+fn parse_rgb<'a>(
+    mut fields: impl Iterator<Item = &'a str>,
+) -> Result<Color, Error> {
+    let mut channel = || {
+        fields
+            .next()
+            .and_then(|f| f.parse::<u8>().ok())
+            .ok_or(Error::BadPaletteFile("bad color entry"))
+    };
+
+    Ok(Color::rgb(channel()?, channel()?, channel()?))
+}
+
+fn read_jasc(data: &[u8]) -> Result<Palette, Error> {
+    let mut lines = text_lines(data)?;
+
+    if lines.next() != Some("JASC-PAL") {
+        return Err(Error::BadPaletteFile("missing JASC-PAL header"));
+    }
+    // Version, which is always 0100.
+    lines.next();
+    let count = lines
+        .next()
+        .and_then(|l| l.parse::<usize>().ok())
+        .ok_or(Error::BadPaletteFile("missing color count"))?;
+
+    let colors = lines
+        .filter(|l| !l.is_empty())
+        .take(count)
+        .map(|l| parse_rgb(l.split_whitespace()))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    if colors.len() != count {
+        return Err(Error::BadPaletteFile("fewer colors than declared"));
+    }
+
+    Ok(Palette::from(colors))
+}
+
+fn read_gimp(data: &[u8]) -> Result<Palette, Error> {
+    let mut lines = text_lines(data)?;
+
+    if lines.next() != Some("GIMP Palette") {
+        return Err(Error::BadPaletteFile("missing GIMP Palette header"));
+    }
+
+    lines
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter(|l| !l.starts_with("Name:") && !l.starts_with("Columns:"))
+        .map(|l| parse_rgb(l.split_whitespace()))
+        .collect()
+}
+
+fn read_act(data: &[u8]) -> Result<Palette, Error> {
+    if data.len() < 768 {
+        return Err(Error::BadPaletteFile("color table is too short"));
+    }
+
+    // The count trailer is optional; without it, all 256 entries are used.
+    let count = match data.get(768..770) {
+        Some(&[hi, lo]) => u16::from_be_bytes([hi, lo]) as usize,
+        _ => 256,
+    };
+
+    Ok(data[..768]
+        .chunks(3)
+        .take(count.min(256))
+        .map(|rgb| Color::rgb(rgb[0], rgb[1], rgb[2]))
+        .collect())
+}
@@ -0,0 +1,136 @@
+// Fire Emblem portrait ("mug") sheets.
+//
+// Portraits are usually drawn as a single 128x112 sheet, in the layout the
+// old FEditor/PortraitFormatter tools used:
+//
+//   (0, 0)     96x80   main portrait
+//   (96, 16)   32x32   minimug (used in menus and the status screen)
+//   (96, 48)   32x16   eyes, half closed
+//   (96, 64)   32x16   eyes, closed
+//   (0, 80)    32x16   mouth frames, six of them in two rows of three
+//
+// The rest of the sheet is unused. Everything shares one 16-color palette.
+// The eye frames are left out for now, so portraits don't blink.
+//
+// The game doesn't store the main portrait as a 96x80 image, but cut into
+// bands on a 32x4-tile sheet: the top and middle 12x4 tiles side by side,
+// then the bottom 12x2 tiles folded into a 6x4 block after them.
+
+use crate::{arrange::TileRect, Error, GBAImage};
+
+pub const SHEET_WIDTH: usize = 128;
+pub const SHEET_HEIGHT: usize = 112;
+
+// Regions of the sheet, in tiles.
+const MAIN: TileRect = TileRect::new(0, 0, 12, 10);
+const MINIMUG: TileRect = TileRect::new(12, 2, 4, 4);
+const MOUTHS: [TileRect; 6] = [
+    TileRect::new(0, 10, 4, 2),
+    TileRect::new(4, 10, 4, 2),
+    TileRect::new(8, 10, 4, 2),
+    TileRect::new(0, 12, 4, 2),
+    TileRect::new(4, 12, 4, 2),
+    TileRect::new(8, 12, 4, 2),
+];
+
+// Where each piece of the main portrait goes in the game's 32x4-tile sheet,
+// as (source rectangle within the portrait, destination tile).
+const MAIN_LAYOUT: [(TileRect, (usize, usize)); 4] = [
+    (TileRect::new(0, 0, 12, 4), (0, 0)),
+    (TileRect::new(0, 4, 12, 4), (12, 0)),
+    (TileRect::new(0, 8, 6, 2), (24, 0)),
+    (TileRect::new(6, 8, 6, 2), (24, 2)),
+];
+
+pub struct Portrait {
+    // The main portrait, in game order (256x32).
+    pub main: GBAImage,
+    // 32x32.
+    pub minimug: GBAImage,
+    // Each 32x16 frame, one above the other.
+    pub mouths: GBAImage,
+    // Where the first mouth frame appears in the main portrait, in tiles, if
+    // it does at all.
+    pub mouth_position: Option<(usize, usize)>,
+}
+
+impl Portrait {
+    pub fn from_sheet(sheet: &GBAImage) -> Result<Self, Error> {
+        if (sheet.width, sheet.height) != (SHEET_WIDTH, SHEET_HEIGHT) {
+            return Err(Error::BadSheetSize(SHEET_WIDTH, SHEET_HEIGHT));
+        }
+        sheet.validate()?;
+
+        let mut main = GBAImage::blank(32 * 8, 4 * 8, sheet.palette.clone())?;
+        for (src, (x, y)) in MAIN_LAYOUT {
+            let src = TileRect::new(
+                MAIN.x + src.x,
+                MAIN.y + src.y,
+                src.width,
+                src.height,
+            );
+            blit(sheet, src, &mut main, x, y)?;
+        }
+
+        let mouth_position = find_block(sheet, MAIN, MOUTHS[0]);
+
+        Ok(Self {
+            main,
+            minimug: stack(sheet, &[MINIMUG])?,
+            mouths: stack(sheet, &MOUTHS)?,
+            mouth_position,
+        })
+    }
+}
+
+// Copies [src] (in tiles) out of [from], with its top-left corner at tile
+// ([x], [y]) of [to].
+fn blit(
+    from: &GBAImage,
+    src: TileRect,
+    to: &mut GBAImage,
+    x: usize,
+    y: usize,
+) -> Result<(), Error> {
+    let view = from.view(src.x * 8, src.y * 8, src.width * 8, src.height * 8);
+    to.blit(&view, x * 8, y * 8)
+}
+
+// Copies same-sized regions out of [sheet], one above the other.
+fn stack(sheet: &GBAImage, rects: &[TileRect]) -> Result<GBAImage, Error> {
+    let (width, height) = (rects[0].width, rects.len() * rects[0].height);
+    let mut image =
+        GBAImage::blank(width * 8, height * 8, sheet.palette.clone())?;
+    for (i, &rect) in rects.iter().enumerate() {
+        blit(sheet, rect, &mut image, 0, i * rect.height)?;
+    }
+    Ok(image)
+}
+
+// Looks for a tile-aligned copy of [needle] within [haystack]. Returns its
+// position relative to [haystack], in tiles.
+fn find_block(
+    sheet: &GBAImage,
+    haystack: TileRect,
+    needle: TileRect,
+) -> Option<(usize, usize)> {
+    let pixels = |x: usize, y: usize| {
+        sheet
+            .view(x * 8, y * 8, needle.width * 8, needle.height * 8)
+            .pixels()
+            .collect::<Vec<_>>()
+    };
+    let target = pixels(needle.x, needle.y);
+    // A blank frame would match any empty part of the portrait.
+    if target.iter().all(|&idx| idx == 0) {
+        return None;
+    }
+
+    let (max_x, max_y) = (
+        haystack.width - needle.width,
+        haystack.height - needle.height,
+    );
+    (0..=max_y)
+        .flat_map(|y| (0..=max_x).map(move |x| (x, y)))
+        .find(|&(x, y)| pixels(haystack.x + x, haystack.y + y) == target)
+}
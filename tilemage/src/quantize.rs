@@ -0,0 +1,332 @@
+// Color reduction for images with more colors than a palette can hold.
+//
+// Everything here happens in the GBA's 15-bit color space: since the hardware
+// can't tell apart colors that only differ in the low 3 bits of each channel,
+// there's no sense in spending palette entries on them. We pick the palette
+// with median cut, then map each pixel to its nearest palette entry,
+// optionally dithering to hide the banding.
+
+use std::collections::HashMap;
+
+use image::{GenericImageView, Pixel};
+
+use crate::{Color, Error, GBAImage, Palette, Transparency};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    None,
+    FloydSteinberg,
+    // 4x4 Bayer matrix
+    Ordered,
+}
+
+#[derive(Clone, Debug)]
+pub struct QuantizeOptions {
+    // Includes the backdrop, if there is one.
+    pub colors: usize,
+    pub dither: Dither,
+    // If set, transparent pixels are given index 0 (holding the backdrop
+    // color) and no opaque pixel is.
+    pub transparency: Option<Transparency>,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> Self {
+        Self {
+            colors: 16,
+            dither: Dither::None,
+            transparency: None,
+        }
+    }
+}
+
+pub struct Quantized {
+    pub image: GBAImage,
+    // Distance between each pixel's original color and the color it was
+    // given, measured in 15-bit color steps. Row-major, like the image.
+    pub error: Vec<f32>,
+}
+
+impl Quantized {
+    pub fn mean_error(&self) -> f32 {
+        if self.error.is_empty() {
+            return 0.0;
+        }
+
+        self.error.iter().sum::<f32>() / self.error.len() as f32
+    }
+
+    pub fn max_error(&self) -> f32 {
+        self.error.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+pub fn reduce_colors<V>(
+    img: &V,
+    opts: &QuantizeOptions,
+) -> Result<Quantized, Error>
+where
+    V: GenericImageView,
+    V::Pixel: Pixel,
+{
+    let backdrop = opts.transparency.and_then(|t| t.backdrop_for(img));
+    let reserved = backdrop.is_some() as usize;
+
+    // The backdrop doesn't count as a color to reduce to.
+    if opts.colors <= reserved {
+        return Err(Error::TooFewColors(opts.colors));
+    }
+    // Indices are stored in a byte, so there's no room for more.
+    if opts.colors > 1 << 8 {
+        return Err(Error::TooManyColorsAsked(opts.colors));
+    }
+
+    let width = img.width() as usize;
+    let height = img.height() as usize;
+
+    // Colors as 5-bit channels, or `None` for transparent pixels.
+    let pixels = img
+        .pixels()
+        .map(|(_x, _y, pix)| match opts.transparency {
+            Some(t) if t.is_transparent(&pix) => None,
+            _ => Some(to_5bit(Color::from(pix))),
+        })
+        .collect::<Vec<_>>();
+
+    let mut histogram: HashMap<[u8; 3], usize> = HashMap::new();
+    for &pixel in pixels.iter().flatten() {
+        *histogram.entry(pixel).or_insert(0) += 1;
+    }
+
+    let palette = median_cut(histogram, opts.colors - reserved);
+
+    // Ordered dithering perturbs each pixel by up to about the typical gap
+    // between palette entries.
+    let spread = palette_spacing(&palette);
+
+    let mut data = Vec::with_capacity(pixels.len());
+    let mut error = Vec::with_capacity(pixels.len());
+    // Error carried forward by Floyd-Steinberg, for this row and the next.
+    let mut carry = vec![[0f32; 3]; 2 * (width + 2)];
+
+    for (i, &pixel) in pixels.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+
+        if x == 0 && y > 0 {
+            carry.copy_within(width + 2.., 0);
+            carry[width + 2..].fill([0.0; 3]);
+        }
+
+        let pixel = match pixel {
+            Some(pixel) => pixel,
+            None => {
+                data.push(0);
+                error.push(0.0);
+                continue;
+            }
+        };
+
+        let mut target = pixel.map(|c| c as f32);
+        match opts.dither {
+            Dither::None => (),
+            Dither::FloydSteinberg => {
+                for (t, e) in target.iter_mut().zip(carry[x + 1]) {
+                    *t += e;
+                }
+            }
+            Dither::Ordered => {
+                const BAYER: [[f32; 4]; 4] = [
+                    [0.0, 8.0, 2.0, 10.0],
+                    [12.0, 4.0, 14.0, 6.0],
+                    [3.0, 11.0, 1.0, 9.0],
+                    [15.0, 7.0, 13.0, 5.0],
+                ];
+                let offset =
+                    ((BAYER[y % 4][x % 4] + 0.5) / 16.0 - 0.5) * spread;
+                for t in target.iter_mut() {
+                    *t += offset;
+                }
+            }
+        }
+
+        let idx = nearest(&palette, target);
+        let chosen = palette[idx].map(|c| c as f32);
+
+        if opts.dither == Dither::FloydSteinberg {
+            let diff = [0, 1, 2].map(|c| target[c] - chosen[c]);
+            let row = width + 2;
+            for (slot, weight) in [
+                (x + 2, 7.0),
+                (row + x, 3.0),
+                (row + x + 1, 5.0),
+                (row + x + 2, 1.0),
+            ] {
+                for c in 0..3 {
+                    carry[slot][c] += diff[c] * weight / 16.0;
+                }
+            }
+        }
+
+        data.push((idx + reserved) as u8);
+        error.push(distance(pixel.map(|c| c as f32), chosen).sqrt());
+    }
+
+    let palette = backdrop
+        .into_iter()
+        .chain(
+            palette
+                .into_iter()
+                .map(|[r, g, b]| Color::rgb(r << 3, g << 3, b << 3)),
+        )
+        .collect::<Palette>();
+
+    Ok(Quantized {
+        image: GBAImage {
+            palette,
+            width,
+            height,
+            data,
+        },
+        error,
+    })
+}
+
+fn to_5bit(color: Color) -> [u8; 3] {
+    [color.r >> 3, color.g >> 3, color.b >> 3]
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|c| (a[c] - b[c]) * (a[c] - b[c])).sum()
+}
+
+fn palette_spacing(palette: &[[u8; 3]]) -> f32 {
+    if palette.len() < 2 {
+        return 0.0;
+    }
+
+    let to_f32 = |color: &[u8; 3]| color.map(|c| c as f32);
+    let total = palette
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            palette
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| i != j)
+                .map(|(_, b)| distance(to_f32(a), to_f32(b)))
+                .fold(f32::MAX, f32::min)
+                .sqrt()
+        })
+        .sum::<f32>();
+
+    total / palette.len() as f32
+}
+
+fn nearest(palette: &[[u8; 3]], target: [f32; 3]) -> usize {
+    palette
+        .iter()
+        .map(|color| distance(color.map(|c| c as f32), target))
+        .enumerate()
+        .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
+        .map(|(idx, _)| idx)
+        .unwrap_or(0)
+}
+
+struct ColorBox {
+    colors: Vec<([u8; 3], usize)>,
+}
+
+impl ColorBox {
+    // Returns the channel with the widest spread, along with that spread.
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..3)
+            .map(|c| {
+                let (min, max) = self
+                    .colors
+                    .iter()
+                    .map(|(color, _)| color[c])
+                    .fold((u8::MAX, 0), |(lo, hi), v| (lo.min(v), hi.max(v)));
+                (c, max.saturating_sub(min))
+            })
+            .max_by_key(|&(c, range)| (range, std::cmp::Reverse(c)))
+            .unwrap()
+    }
+
+    fn population(&self) -> usize {
+        self.colors.iter().map(|(_, count)| count).sum()
+    }
+
+    // Splits at the population-weighted median of the widest channel.
+    fn split(mut self) -> (Self, Self) {
+        let (channel, _) = self.widest_channel();
+        self.colors.sort_by_key(|(color, _)| color[channel]);
+
+        let half = self.population() / 2;
+        let mut seen = 0;
+        let mut at = 1;
+        for (i, (_, count)) in self.colors.iter().enumerate() {
+            seen += count;
+            if seen > half {
+                at = i.max(1);
+                break;
+            }
+        }
+        let at = at.min(self.colors.len() - 1);
+
+        let rest = self.colors.split_off(at);
+        (self, Self { colors: rest })
+    }
+
+    fn average(&self) -> [u8; 3] {
+        let total = self.population().max(1);
+        [0, 1, 2].map(|c| {
+            let sum = self
+                .colors
+                .iter()
+                .map(|(color, count)| color[c] as usize * count)
+                .sum::<usize>();
+            ((sum + total / 2) / total) as u8
+        })
+    }
+}
+
+fn median_cut(
+    histogram: HashMap<[u8; 3], usize>,
+    colors: usize,
+) -> Vec<[u8; 3]> {
+    let mut initial = histogram.into_iter().collect::<Vec<_>>();
+    // Keep things deterministic regardless of hash order.
+    initial.sort();
+
+    if initial.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    let mut boxes = vec![ColorBox { colors: initial }];
+
+    while boxes.len() < colors {
+        let candidate = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.colors.len() > 1)
+            .max_by_key(|(i, b)| {
+                (
+                    b.widest_channel().1 as usize * b.population(),
+                    std::cmp::Reverse(*i),
+                )
+            })
+            .map(|(i, _)| i);
+
+        let i = match candidate {
+            Some(i) => i,
+            None => break,
+        };
+
+        let (left, right) = boxes.swap_remove(i).split();
+        boxes.push(left);
+        boxes.push(right);
+    }
+
+    boxes.iter().map(ColorBox::average).collect()
+}
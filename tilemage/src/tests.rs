@@ -0,0 +1,1171 @@
+use image::{Rgb, RgbImage};
+
+use super::*;
+
+// Builds an image where each 8x8 tile is filled with a horizontal gradient of
+// `colors_per_tile` colors, none of which are shared between tiles.
+fn distinct_tiles(tiles_wide: u32, colors_per_tile: u32) -> RgbImage {
+    RgbImage::from_fn(tiles_wide * 8, 8, |x, _y| {
+        let tile = x / 8;
+        let shade = (x % 8) % colors_per_tile;
+        Rgb([(tile * 8) as u8, (shade * 8) as u8, 0xF8])
+    })
+}
+
+#[test]
+fn multi_palette_splits_colors_across_banks() {
+    let img = distinct_tiles(4, 7);
+    let mpi =
+        bg::MultiPaletteImage::from_generic_image(&img, 16, None).unwrap();
+    mpi.validate().unwrap();
+
+    // 7 colors per tile, so two tiles fit in each bank's 15 opaque slots.
+    assert_eq!(mpi.palette_count(), 2);
+    assert_eq!(mpi.tile_palettes(), [0, 0, 1, 1]);
+
+    for (x, y, pix) in img.enumerate_pixels() {
+        let (x, y) = (x as usize, y as usize);
+        assert_eq!(mpi.image.color_at(x, y).unwrap(), Color::from(*pix));
+        // Nothing is opaque, so nothing may use the transparent index.
+        assert_ne!(mpi.image.pixel_at(x, y).unwrap() % 16, 0);
+    }
+
+    // Tile (1, 0) is in bank 0, so it can't borrow a color from bank 1.
+    let mut mpi = mpi;
+    mpi.image.data[8] = 17;
+    assert!(matches!(
+        mpi.validate(),
+        Err(Error::TileOutsideBank(1, 0, 0))
+    ));
+}
+
+#[test]
+fn multi_palette_keeps_index_zero_for_transparency() {
+    use image::{Rgba, RgbaImage};
+
+    // Mostly transparent, with an opaque black outline in both tiles, and two
+    // reds the GBA can't tell apart.
+    let img = RgbaImage::from_fn(16, 8, |x, _y| match x % 8 {
+        0 => Rgba([0, 0, 0, 0xFF]),
+        1 => Rgba([0xF8, 0, 0, 0xFF]),
+        2 => Rgba([0xFF, 0x07, 0x07, 0xFF]),
+        _ => Rgba([0, 0, 0, 0]),
+    });
+    let transparency = Some(Transparency {
+        alpha_threshold: 0,
+        backdrop: Some(Color::rgb(0xF8, 0, 0xF8)),
+    });
+    let mpi = bg::MultiPaletteImage::from_generic_image(&img, 16, transparency)
+        .unwrap();
+    mpi.validate().unwrap();
+
+    assert_eq!(mpi.tile_palettes(), [0, 0]);
+    assert_eq!(mpi.image.palette.lookup(0), Some(Color::rgb(0xF8, 0, 0xF8)));
+    for x in [0, 8] {
+        assert_eq!(mpi.image.pixel_at(x + 5, 0), Some(0));
+        assert_ne!(mpi.image.pixel_at(x, 0), Some(0));
+        assert_eq!(mpi.image.pixel_at(x + 1, 0), mpi.image.pixel_at(x + 2, 0));
+    }
+}
+
+#[test]
+fn multi_palette_respects_limit() {
+    let img = distinct_tiles(4, 8);
+    assert!(matches!(
+        bg::MultiPaletteImage::from_generic_image(&img, 1, None),
+        Err(Error::TooManyColors(_))
+    ));
+}
+
+#[test]
+fn tilemap_dedupes_flipped_tiles() {
+    // Tile 1 is tile 0 mirrored horizontally, tile 2 is tile 0 again.
+    let img = RgbImage::from_fn(24, 8, |x, y| {
+        let (tile, x) = (x / 8, x % 8);
+        let x = if tile == 1 { 7 - x } else { x };
+        if x == 0 && y < 4 {
+            Rgb([0xF8, 0, 0])
+        } else {
+            Rgb([0, 0, 0])
+        }
+    });
+    let image = GBAImage::with_inferred_palette(&img).unwrap();
+
+    let (tiles, map) =
+        bg::build_tilemap(&image, None, &Default::default()).unwrap();
+    assert_eq!(tiles.height, 8);
+    assert_eq!(
+        map.entry_at(1, 0).map(|e| (e.tile, e.hflip)),
+        Some((0, true))
+    );
+    assert_eq!(
+        map.entry_at(2, 0).map(|e| (e.tile, e.hflip)),
+        Some((0, false))
+    );
+
+    let opts = bg::TilemapOptions {
+        flips: false,
+        size: Some((32, 32)),
+    };
+    let (tiles, map) = bg::build_tilemap(&image, None, &opts).unwrap();
+    // Two distinct tiles, plus a blank one for padding.
+    assert_eq!(tiles.height, 24);
+    assert_eq!(map.encode().len(), 32 * 32 * 2);
+    assert_eq!(map.entry_at(5, 5).map(|e| e.tile), Some(2));
+}
+
+#[test]
+fn affine_map_uses_8bpp_tiles() {
+    // 40 colors, so the tiles can't fit a 4bpp palette. Tile 1 is tile 0
+    // mirrored, which affine maps can't take advantage of.
+    let img = RgbImage::from_fn(24, 8, |x, y| {
+        let (tile, x) = (x / 8, x % 8);
+        let x = if tile == 1 { 7 - x } else { x };
+        Rgb([(x * 8 + y) as u8 * 4, 0, if tile == 2 { 0x80 } else { 0 }])
+    });
+    let image = GBAImage::with_inferred_palette(&img).unwrap();
+    assert!(image.validate().is_err());
+
+    let (tiles, map) = affine::build_affine_map(&image, None).unwrap();
+    assert_eq!(map.size, 16);
+    // Three distinct tiles, plus a blank one for padding.
+    assert_eq!(tiles.height, 32);
+    assert_eq!(map.encode().len(), 16 * 16);
+    assert_eq!(map.entry_at(1, 0), Some(1));
+    assert_eq!(map.entry_at(5, 5), Some(3));
+
+    let bytes = encode_tiles_with_depth(tiles.tiles(), BitDepth::Eight);
+    assert_eq!(bytes.len(), 4 * 64);
+    let decoded = GBAImage::from_tiles_with_depth(
+        &bytes,
+        tiles.palette.clone(),
+        1,
+        BitDepth::Eight,
+    )
+    .unwrap();
+    assert_eq!(decoded.data, tiles.data);
+
+    assert!(affine::build_affine_map(&image, Some(8)).is_err());
+}
+
+#[test]
+fn from_tiles_roundtrips_encode_tiles() {
+    let bytes = (0..=255u8).cycle().take(32 * 6).collect::<Vec<_>>();
+    let palette = (0..16).map(|i| Color::rgb(i * 8, 0, 0)).collect();
+
+    // Six tiles at four tiles wide leaves two blank tiles of padding.
+    let image = GBAImage::from_tiles(&bytes[..], palette, 4).unwrap();
+    assert_eq!((image.width, image.height), (32, 16));
+
+    let encoded = encode_tiles(image.tiles());
+    assert_eq!(&encoded[..bytes.len()], &bytes[..]);
+    assert!(encoded[bytes.len()..].iter().all(|&b| b == 0));
+}
+
+#[test]
+fn from_tiles_rejects_indices_past_the_palette() {
+    let palette = (0..16)
+        .map(|i| Color::rgb(i * 8, 0, 0))
+        .collect::<Palette>();
+    let bytes = (0..64).collect::<Vec<u8>>();
+    assert!(matches!(
+        GBAImage::from_tiles_with_depth(&bytes, palette, 1, BitDepth::Eight),
+        Err(Error::IndexOutOfRange(16, 16))
+    ));
+
+    // With the whole palette there, every index survives a trip through PNG.
+    let palette = (0..64)
+        .map(|i| Color::rgb(i * 4, 0, 0))
+        .collect::<Palette>();
+    let image =
+        GBAImage::from_tiles_with_depth(&bytes, palette, 1, BitDepth::Eight)
+            .unwrap();
+    let png = image.encode_png().unwrap();
+    let decoded = GBAImage::from_indexed_png(&png, 256, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(decoded.data, bytes);
+}
+
+#[test]
+fn from_tiles_pads_a_partial_tile() {
+    let palette = (0..16)
+        .map(|i| Color::rgb(i * 8, 0, 0))
+        .collect::<Palette>();
+    // One whole tile and the first row of another.
+    let bytes = [0x11; 36];
+    let image = GBAImage::from_tiles(&bytes, palette, 2).unwrap();
+    assert_eq!((image.width, image.height), (16, 8));
+    assert_eq!(image.pixel_at(8, 0), Some(1));
+    assert_eq!(image.pixel_at(8, 1), Some(0));
+}
+
+#[test]
+fn convert_rejects_more_than_256_colors() {
+    // Indices are stored in a byte, so conversion can't hold off until
+    // [GBAImage::validate] to say there are too many.
+    let img = RgbImage::from_fn(64, 8, |x, y| {
+        Rgb([(x * 4) as u8, (y * 32) as u8, 0])
+    });
+    let opts = ConvertOptions {
+        depth: BitDepth::Eight,
+        ..Default::default()
+    };
+    let Err(Error::TooManyColors(diagnosis)) = GBAImage::convert(&img, &opts)
+    else {
+        panic!("expected too many colors");
+    };
+    assert_eq!(diagnosis.colors.len(), 256);
+
+    let img = RgbImage::from_fn(64, 4, |x, y| {
+        Rgb([(x * 4) as u8, (y * 32) as u8, 0])
+    });
+    GBAImage::convert(&img, &opts).unwrap();
+}
+
+#[test]
+fn reduce_colors_keeps_small_palettes_exact() {
+    let img = distinct_tiles(2, 4);
+    let opts = quantize::QuantizeOptions {
+        colors: 16,
+        dither: quantize::Dither::FloydSteinberg,
+        transparency: None,
+    };
+    let reduced = quantize::reduce_colors(&img, &opts).unwrap();
+    assert_eq!(reduced.image.palette.len(), 8);
+    assert_eq!(reduced.max_error(), 0.0);
+}
+
+#[test]
+fn reduce_colors_limits_palette() {
+    let img = RgbImage::from_fn(64, 8, |x, _y| Rgb([(x * 4) as u8, 0, 0]));
+    for dither in [
+        quantize::Dither::None,
+        quantize::Dither::FloydSteinberg,
+        quantize::Dither::Ordered,
+    ] {
+        let opts = quantize::QuantizeOptions {
+            colors: 16,
+            dither,
+            ..Default::default()
+        };
+        let reduced = quantize::reduce_colors(&img, &opts).unwrap();
+        assert_eq!(reduced.image.palette.len(), 16);
+        reduced.image.validate().unwrap();
+        assert!(reduced.mean_error() < 2.0);
+    }
+
+    // With the backdrop taking index 0, one color leaves none for the image.
+    let opts = quantize::QuantizeOptions {
+        colors: 1,
+        transparency: Some(Transparency {
+            alpha_threshold: 0,
+            backdrop: Some(Color::rgb(0, 0, 0)),
+        }),
+        ..Default::default()
+    };
+    assert!(matches!(
+        quantize::reduce_colors(&img, &opts),
+        Err(Error::TooFewColors(1))
+    ));
+
+    // Nor can indices go past a byte.
+    let opts = quantize::QuantizeOptions {
+        colors: 257,
+        ..Default::default()
+    };
+    assert!(matches!(
+        quantize::reduce_colors(&img, &opts),
+        Err(Error::TooManyColorsAsked(257))
+    ));
+}
+
+#[test]
+fn wide_subpixels_are_trimmed() {
+    use image::{ImageBuffer, Luma, LumaA};
+
+    assert_eq!(
+        Color::from(Rgb([0x12FFu16, 0xAB00, 0xFFFF])),
+        Color::rgb(0x12, 0xAB, 0xFF)
+    );
+    assert_eq!(
+        Color::from(Rgb([0.0f32, 1.0, 0.5])),
+        Color::rgb(0, 255, 128)
+    );
+    assert_eq!(
+        Color::from(LumaA([0x40u8, 0xFF])),
+        Color::rgb(0x40, 0x40, 0x40)
+    );
+
+    let img: ImageBuffer<Luma<u16>, Vec<u16>> =
+        ImageBuffer::from_fn(8, 8, |x, _y| Luma([(x as u16) << 12 | 0xFF]));
+    let image = GBAImage::with_inferred_palette(&img).unwrap();
+    assert_eq!(image.palette.len(), 8);
+    assert_eq!(image.color_at(7, 0), Some(Color::rgb(0x70, 0x70, 0x70)));
+
+    let dynamic = to_8bit(DynamicImage::ImageLuma16(img));
+    assert!(matches!(dynamic, DynamicImage::ImageLuma8(_)));
+    let image = GBAImage::with_inferred_palette(&dynamic).unwrap();
+    assert_eq!(image.color_at(7, 0), Some(Color::rgb(0x70, 0x70, 0x70)));
+}
+
+#[test]
+fn transparent_pixels_use_index_zero() {
+    use image::{Rgba, RgbaImage};
+
+    // Transparent pixels with two different (invisible) colors.
+    let img = RgbaImage::from_fn(8, 8, |x, y| match (x, y) {
+        (0, _) => Rgba([0xF8, 0, 0, 0xFF]),
+        (1, _) => Rgba([0, 0xF8, 0, 0xFF]),
+        (_, 0) => Rgba([0x10, 0x20, 0x30, 0]),
+        _ => Rgba([0, 0, 0, 0]),
+    });
+
+    let image = GBAImage::with_inferred_palette(&img).unwrap();
+    assert_eq!(image.palette.len(), 4);
+
+    let opts = ConvertOptions {
+        transparency: Some(Transparency::default()),
+        ..Default::default()
+    };
+    let image = GBAImage::convert(&img, &opts).unwrap();
+    assert_eq!(image.palette.len(), 3);
+    assert_eq!(image.pixel_at(5, 5), Some(0));
+    assert_eq!(image.pixel_at(5, 0), Some(0));
+    assert_eq!(image.palette.lookup(0), Some(Color::rgb(0x10, 0x20, 0x30)));
+
+    let backdrop = Color::rgb(0xF8, 0, 0xF8);
+    let opts = ConvertOptions {
+        transparency: Some(Transparency {
+            alpha_threshold: 0,
+            backdrop: Some(backdrop),
+        }),
+        ..Default::default()
+    };
+    let image = GBAImage::convert(&img, &opts).unwrap();
+    assert_eq!(image.palette.lookup(0), Some(backdrop));
+    assert_eq!(image.pixel_at(0, 0), Some(1));
+}
+
+#[test]
+fn opaque_pixels_never_share_the_backdrop_index() {
+    use image::{Rgba, RgbaImage};
+
+    // An opaque black outline on a (transparent) black background.
+    let img = RgbaImage::from_fn(8, 8, |x, _y| match x {
+        0 => Rgba([0, 0, 0, 0xFF]),
+        1 => Rgba([0xF8, 0, 0, 0xFF]),
+        _ => Rgba([0, 0, 0, 0]),
+    });
+    let opts = ConvertOptions {
+        transparency: Some(Transparency::default()),
+        ..Default::default()
+    };
+    let image = GBAImage::convert(&img, &opts).unwrap();
+    let black = Color::rgb(0, 0, 0);
+    assert_eq!(image.palette.0, [black, black, Color::rgb(0xF8, 0, 0)]);
+    assert_eq!(image.pixel_at(0, 0), Some(1));
+    assert_eq!(image.pixel_at(1, 0), Some(2));
+    assert_eq!(image.pixel_at(2, 0), Some(0));
+}
+
+#[test]
+fn indexed_png_keeps_duplicate_entries() {
+    let red = Color::rgb(0xF8, 0, 0);
+    let palette = Palette::from(vec![red, red, Color::rgb(0, 0, 0xF8)]);
+    let data = (0..64).map(|i| i % 3).collect::<Vec<usize>>();
+    let image = GBAImage {
+        palette,
+        width: 8,
+        height: 8,
+        data: data.iter().map(|&idx| idx as u8).collect(),
+    };
+
+    let png = image.encode_png().unwrap();
+    let decoded = GBAImage::from_indexed_png(&png[..], 16, None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(decoded.palette.len(), 3);
+    assert_eq!(decoded.pixels().collect::<Vec<_>>(), data);
+
+    let converted = convert_image(&png[..], None, None).unwrap();
+    assert_eq!(converted.pixels().collect::<Vec<_>>(), data);
+}
+
+#[test]
+fn indexed_png_applies_transparency() {
+    // Index 2 is transparent; index 0 is an ordinary opaque color.
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, 4, 1);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(vec![0, 0, 0, 0xF8, 0, 0, 0, 0xF8, 0]);
+        encoder.set_trns(vec![0xFF, 0xFF, 0]);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[1, 2, 0, 2]).unwrap();
+    }
+
+    let opts = |transparency| ConvertOptions {
+        transparency,
+        ..Default::default()
+    };
+    let ignored = convert_image_with_options(&png[..], None, opts(None));
+    assert_eq!(ignored.unwrap().data, [1, 2, 0, 2]);
+
+    let image = convert_image_with_options(
+        &png[..],
+        None,
+        opts(Some(Transparency::default())),
+    )
+    .unwrap();
+    assert_eq!(image.data, [1, 0, 0, 0]);
+    // Index 0 takes the transparent entry's color, like an RGBA image's
+    // backdrop.
+    assert_eq!(image.palette.lookup(0), Some(Color::rgb(0, 0xF8, 0)));
+
+    let backdrop = Color::rgb(0xF8, 0, 0xF8);
+    let image = convert_image_with_options(
+        &png[..],
+        None,
+        opts(Some(Transparency {
+            alpha_threshold: 0,
+            backdrop: Some(backdrop),
+        })),
+    )
+    .unwrap();
+    assert_eq!(image.palette.lookup(0), Some(backdrop));
+}
+
+#[test]
+fn indexed_png_rejects_out_of_range_indices() {
+    let palette = (0..20).map(|i| Color::rgb(i * 8, 0, 0)).collect();
+    let image = GBAImage {
+        palette,
+        width: 8,
+        height: 8,
+        data: (0..64).map(|i| i % 20).collect(),
+    };
+
+    let png = image.encode_png().unwrap();
+    assert!(matches!(
+        GBAImage::from_indexed_png(&png[..], 16, None),
+        Err(Error::IndexOutOfRange(16, 16))
+    ));
+    assert_eq!(
+        GBAImage::from_indexed_png(&png[..], 256, None)
+            .unwrap()
+            .map(|image| image.palette.len()),
+        Some(20)
+    );
+}
+
+#[test]
+fn palette_formats_roundtrip() {
+    use palfile::PaletteFormat::*;
+
+    let palette = (0..16)
+        .map(|i| Color::rgb(i * 8, 0xF8 - i * 8, 0x80))
+        .collect::<Palette>();
+
+    for format in [Raw, Hex, JascPal, Gimp, Act] {
+        let written = palette.write(format);
+        let read = Palette::read(&written[..], format).unwrap();
+        assert_eq!(read.0, palette.0, "{:?}", format);
+    }
+
+    // Hex strings hold exactly 16 colors, so longer palettes take several
+    // lines, and the last is padded.
+    let long = (0..20)
+        .map(|i| Color::rgb(i * 8, 0, 0))
+        .collect::<Palette>();
+    let written = long.write(Hex);
+    assert_eq!(written.iter().filter(|&&b| b == b'\n').count(), 2);
+    let read = Palette::read(&written[..], Hex).unwrap();
+    assert_eq!(read.len(), 32);
+    assert_eq!(read.0[..20], long.0[..]);
+    assert!(read.0[20..].iter().all(|&c| c == Color::rgb(0, 0, 0)));
+
+    assert_eq!(
+        palfile::PaletteFormat::sniff("x.pal", b"JASC-PAL\r\n"),
+        Some(JascPal)
+    );
+    assert_eq!(palfile::PaletteFormat::sniff("x.pal", &[0, 0]), Some(Raw));
+}
+
+#[test]
+fn sprite_pieces_cover_visible_pixels() {
+    use image::{Rgba, RgbaImage};
+
+    // A 24x16 block plus a lone pixel off to the side, low down in a cell
+    // that hangs off the right edge, all on a transparent background. The
+    // block's 15 colors and the backdrop fill one palette.
+    let img = RgbaImage::from_fn(44, 32, |x, y| {
+        if (x < 24 && y < 16) || (x, y) == (42, 29) {
+            Rgba([0xF8, (x % 5 * 48) as u8, (y % 3 * 96) as u8, 0xFF])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    });
+    let opts = ConvertOptions {
+        transparency: Some(Transparency::default()),
+        ..Default::default()
+    };
+    let image = GBAImage::convert(&img, &opts).unwrap();
+
+    for mapping in [obj::Mapping::OneD, obj::Mapping::TwoD] {
+        let opts = obj::SpriteOptions {
+            mapping,
+            origin: (8, 8),
+            palette: 0,
+        };
+        let sprite = obj::Sprite::from_image(&image, &opts).unwrap();
+
+        // Redraw the sprite from its pieces and check that it matches.
+        let stride = match mapping {
+            obj::Mapping::OneD => None,
+            obj::Mapping::TwoD => Some(32),
+        };
+        let mut drawn = vec![vec![0; 44]; 32];
+        for piece in sprite.pieces.iter() {
+            for ty in 0..piece.shape.height {
+                for tx in 0..piece.shape.width {
+                    let tile = match stride {
+                        None => piece.tile + ty * piece.shape.width + tx,
+                        Some(stride) => piece.tile + ty * stride + tx,
+                    };
+                    let (sx, sy) = match stride {
+                        None => (0, tile * 8),
+                        Some(stride) => {
+                            ((tile % stride) * 8, (tile / stride) * 8)
+                        }
+                    };
+                    for i in 0..64 {
+                        let x = piece.x + 8 + (tx * 8 + i % 8) as i32;
+                        let y = piece.y + 8 + (ty * 8 + i / 8) as i32;
+                        if (0..44).contains(&x) && (0..32).contains(&y) {
+                            drawn[y as usize][x as usize] = sprite
+                                .sheet
+                                .pixel_at(sx + i % 8, sy + i / 8)
+                                .unwrap();
+                        }
+                    }
+                }
+            }
+        }
+
+        for (y, row) in drawn.iter().enumerate() {
+            for (x, &idx) in row.iter().enumerate() {
+                assert_eq!(Some(idx), image.pixel_at(x, y));
+            }
+        }
+        assert_eq!(sprite.encode_oam().len(), 2 + 6 * sprite.pieces.len());
+    }
+
+    // A 16th opaque color doesn't fit alongside the backdrop.
+    let mut img = img;
+    img.put_pixel(30, 20, Rgba([0, 0xF8, 0, 0xFF]));
+    let image = GBAImage::convert(&img, &opts).unwrap();
+    let opts = obj::SpriteOptions {
+        mapping: obj::Mapping::OneD,
+        origin: (0, 0),
+        palette: 0,
+    };
+    assert!(matches!(
+        obj::Sprite::from_image(&image, &opts),
+        Err(Error::TooManyColors(_))
+    ));
+}
+
+#[test]
+fn arrangements_reorder_tiles() {
+    use arrange::{Arrangement, TileRect};
+
+    // 4x2 tiles, each filled with its own row-major number.
+    let image = GBAImage {
+        palette: (0..8).map(|i| Color::rgb(i * 8, 0, 0)).collect(),
+        width: 32,
+        height: 16,
+        data: (0..32 * 16usize)
+            .map(|i| ((i / 256) * 4 + (i % 32) / 8) as u8)
+            .collect(),
+    };
+    let order = |arrangement: &Arrangement| {
+        image
+            .arranged_tiles(arrangement)
+            .unwrap()
+            .map(|tile| tile.pixel_at(0, 0).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(order(&Arrangement::RowMajor), (0..8).collect::<Vec<_>>());
+    assert_eq!(order(&Arrangement::ColumnMajor), [0, 4, 1, 5, 2, 6, 3, 7]);
+    assert_eq!(order(&Arrangement::metatiles()), [0, 1, 4, 5, 2, 3, 6, 7]);
+    assert_eq!(
+        order(&Arrangement::Regions(vec![
+            TileRect::new(3, 0, 1, 2),
+            TileRect::new(0, 1, 2, 1),
+        ])),
+        [3, 7, 4, 5]
+    );
+
+    let bad = Arrangement::Regions(vec![TileRect::new(3, 0, 2, 1)]);
+    assert!(image.arranged_tiles(&bad).is_err());
+    let bad = Arrangement::Blocks {
+        width: 3,
+        height: 1,
+    };
+    assert!(image.arranged_tiles(&bad).is_err());
+}
+
+#[test]
+fn portrait_sheet_is_split_into_parts() {
+    // Each tile's first row holds its x coordinate, and its second row its
+    // y coordinate, so every tile is distinct.
+    let mut data = (0..112 * 128)
+        .map(|i| match (i / 128) % 8 {
+            0 => (i % 128) / 8,
+            1 => i / 128 / 8,
+            _ => 0,
+        })
+        .collect::<Vec<_>>();
+    let sheet = |data: Vec<usize>| GBAImage {
+        palette: (0..16).map(|i| Color::rgb(i * 8, 0, 0)).collect(),
+        width: 128,
+        height: 112,
+        data: data.into_iter().map(|idx| idx as u8).collect(),
+    };
+
+    let portrait =
+        portrait::Portrait::from_sheet(&sheet(data.clone())).unwrap();
+    assert_eq!((portrait.main.width, portrait.main.height), (256, 32));
+    assert_eq!((portrait.mouths.width, portrait.mouths.height), (32, 96));
+    // The middle band of the portrait starts at tile (12, 0).
+    assert_eq!(
+        portrait.main.pixel_at(96, 0),
+        sheet(data.clone()).pixel_at(0, 32)
+    );
+    // The second half of the bottom band is folded under the first.
+    assert_eq!(
+        portrait.main.pixel_at(192, 16),
+        sheet(data.clone()).pixel_at(48, 64)
+    );
+    assert_eq!(portrait.minimug.pixel_at(0, 0), Some(12));
+    assert_eq!(portrait.mouth_position, None);
+
+    // Copy the first mouth frame into the portrait at tile (3, 5).
+    for y in 0..16 {
+        for x in 0..32 {
+            data[(40 + y) * 128 + 24 + x] = data[(80 + y) * 128 + x];
+        }
+    }
+    let portrait = portrait::Portrait::from_sheet(&sheet(data)).unwrap();
+    assert_eq!(portrait.mouth_position, Some((3, 5)));
+
+    let small = GBAImage::from_tiles(
+        &[0; 32],
+        Palette::from(vec![Color::rgb(0, 0, 0)]),
+        1,
+    )
+    .unwrap();
+    assert!(portrait::Portrait::from_sheet(&small).is_err());
+}
+
+// Any decompression error; the tests only check there aren't any.
+#[derive(Debug)]
+struct Lz77Error;
+
+impl gbalz77::BadBlockErrorHandler for Lz77Error {
+    fn bad_reference(_block_num: usize, _offs: usize) -> Self {
+        Self
+    }
+}
+
+impl gbalz77::DecompressErrorHandler for Lz77Error {
+    fn data_too_short() -> Self {
+        Self
+    }
+
+    fn bad_header() -> Self {
+        Self
+    }
+
+    fn unexpected_eof(_expected: &'static str) -> Self {
+        Self
+    }
+}
+
+#[test]
+fn banim_script_compiles() {
+    let mut script = String::new();
+    for mode in 1..=12 {
+        script.push_str(&format!("/// - Mode {}\n", mode));
+        if mode == 1 {
+            script.push_str("C01\nS0034\n3 p- a.png\n2 p- b.png\n3 p- a.png\n");
+        }
+        script.push_str("~~~\n\n");
+    }
+
+    let modes = banim::parse_script(&script).unwrap();
+    assert_eq!(modes.len(), 12);
+    assert_eq!(banim::frame_images(&modes), ["a.png", "b.png"]);
+    assert_eq!(modes[0].commands[1], banim::Command::Sound(0x34));
+
+    // One 8x8 sprite in each frame, at different spots.
+    let frame = |x: usize| {
+        let mut data = vec![0; 64 * 32];
+        data[x] = 1;
+        GBAImage {
+            palette: Palette::from(vec![Color::rgb(0, 0, 0); 2]),
+            width: 64,
+            height: 32,
+            data,
+        }
+    };
+    let frames = [("a.png", frame(0)), ("b.png", frame(8))]
+        .into_iter()
+        .map(|(name, image)| (name.to_string(), image))
+        .collect();
+    let opts = banim::BanimOptions { origin: (0, 0) };
+    let palette = Palette::from(vec![Color::rgb(0, 0, 0); 2]);
+    let anim = banim::compile(&modes, &frames, &palette, &opts).unwrap();
+
+    // Mode 1 starts the script, and the rest each hold only an end marker.
+    let sections = &anim.data[anim.sections..anim.sections + 48];
+    let section = |i: usize| {
+        u32::from_le_bytes(sections[i * 4..i * 4 + 4].try_into().unwrap())
+    };
+    assert_eq!(section(0), 0);
+    assert_eq!(section(1), 4 * (2 + 3 * 3 + 1));
+    assert_eq!(section(2), section(1) + 4);
+    assert_eq!(anim.data.len() % 4, 0);
+
+    // Both frames fit on the first sheet, at the start of the data, and the
+    // stream keeps each frame's pointer where the installer expects it.
+    assert_eq!(anim.sheet_pointers, [12, 24, 36]);
+    let (stream, pointers) = anim.script_stream();
+    let (script, errs) = gbalz77::decompress::<Lz77Error>(&stream[..]);
+    assert!(errs.is_empty());
+    assert_eq!(script, anim.script);
+    for (&i, &j) in anim.sheet_pointers.iter().zip(pointers.iter()) {
+        assert_eq!(script[i..i + 4], [0; 4]);
+        assert_eq!(stream[j..j + 4], [0; 4]);
+    }
+    assert_eq!(pointers[0], 4 + 9 + 5);
+
+    let missing = HashMap::new();
+    assert!(banim::compile(&modes, &missing, &palette, &opts).is_err());
+    assert!(banim::parse_script("/// - Mode 1\n~~~\n").is_err());
+    assert!(banim::parse_script("/// - Mode 1\nX01\n").is_err());
+}
+
+#[test]
+fn chipset_splits_metatiles() {
+    // Two metatiles, the second a mirror image of the first.
+    let left = distinct_tiles(2, 4);
+    let img = RgbImage::from_fn(32, 16, |x, y| {
+        let x = if x < 16 { x } else { 31 - x };
+        *left.get_pixel(x % 16, y % 8)
+    });
+
+    let chipset = chipset::Chipset::from_image(&img, None).unwrap();
+    assert_eq!(chipset.metatiles.len(), 2);
+    // Only two distinct tiles, since the bottom row repeats the top.
+    assert_eq!(chipset.tiles.height, 16);
+
+    let [tl, tr, bl, _br] = chipset.metatiles[0];
+    assert_eq!(tl, bl);
+    let mirrored = chipset.metatiles[1][0];
+    assert_eq!((mirrored.tile, mirrored.hflip), (tr.tile, true));
+
+    let (_obj1, obj2) = chipset.encode_obj();
+    assert!(obj2.is_none());
+
+    // One bank in use, but the group is always a full five.
+    assert_eq!(chipset.palettes.len(), 16);
+    assert_eq!(chipset.encode_palettes().len(), chipset::MAX_PALETTES * 32);
+
+    // Without transparency, every pixel stays opaque, however common its
+    // color.
+    assert!(chipset.tiles.data.iter().all(|&idx| idx % 16 != 0));
+
+    let odd = RgbImage::new(24, 16);
+    assert!(chipset::Chipset::from_image(&odd, None).is_err());
+}
+
+#[test]
+fn icons_are_sliced_and_encoded_by_quadrant() {
+    // Two icons, each quadrant a solid color, then a blank one.
+    let mut sheet = GBAImage {
+        palette: (0..17).map(|i| Color::rgb(i * 8, 0, 0)).collect(),
+        width: 48,
+        height: 16,
+        data: (0..48 * 16usize)
+            .map(|i| {
+                let (x, y) = (i % 48, i / 48);
+                if x >= 32 {
+                    0
+                } else {
+                    ((x / 16) * 4 + (y / 8) * 2 + (x % 16) / 8 + 1) as u8
+                }
+            })
+            .collect(),
+    };
+
+    let found = icons::icons(&sheet).unwrap();
+    assert_eq!(found.len(), 3);
+    assert!(!icons::is_blank(&found[1]));
+    assert!(icons::is_blank(&found[2]));
+
+    let bytes = icons::encode_icon(&found[1]);
+    assert_eq!(bytes.len(), icons::ICON_BYTES);
+    // Top left, top right, bottom left, bottom right.
+    let quadrants = bytes.chunks(32).map(|tile| tile[0]).collect::<Vec<_>>();
+    assert_eq!(quadrants, [0x55, 0x66, 0x77, 0x88]);
+
+    // The first icon holds index 16, but it's the third that brings in the
+    // 17th color.
+    sheet.data[0] = 16;
+    for (x, idx) in (9..16).enumerate() {
+        sheet.data[32 + x] = idx;
+    }
+    assert!(matches!(
+        icons::compact(&mut sheet),
+        Err(Error::IconColors(2))
+    ));
+
+    // Left as it is, index 16 would be masked down to some other color.
+    // Compacting renumbers it into the first 16.
+    assert!(matches!(icons::icons(&sheet), Err(Error::IconIndex(0))));
+    sheet.data[32..39].fill(0);
+    icons::compact(&mut sheet).unwrap();
+    assert_eq!(sheet.palette.len(), 10);
+    assert_eq!(sheet.palette.lookup(1), Some(Color::rgb(16 * 8, 0, 0)));
+    assert!(icons::icons(&sheet).is_ok());
+}
+
+#[test]
+fn remap_matches_gba_colors_then_nearest() {
+    let palette =
+        Palette::from(vec![Color::rgb(0, 0, 0), Color::rgb(0xF8, 0x80, 0)]);
+
+    // Exact, identical on the GBA, a step off, and identical again.
+    let mut img = RgbImage::from_pixel(4, 1, Rgb([0, 0, 0]));
+    img.put_pixel(1, 0, Rgb([0xFC, 0x84, 0x03]));
+    img.put_pixel(2, 0, Rgb([0xF0, 0x88, 0]));
+    img.put_pixel(3, 0, Rgb([0xFC, 0x84, 0x03]));
+
+    let opts = ConvertOptions {
+        palette: Some(palette.clone()),
+        remap: Some(Remap { tolerance: 1 }),
+        ..Default::default()
+    };
+    let (image, report) = GBAImage::convert_with_report(&img, &opts).unwrap();
+    assert_eq!(image.data, [0, 1, 1, 1]);
+    assert_eq!(report.len(), 2);
+    assert_eq!(
+        report[0],
+        RemappedColor {
+            color: Color::rgb(0xFC, 0x84, 0x03),
+            index: 1,
+            count: 2,
+            first: (1, 0),
+        }
+    );
+    assert_eq!(report[1].first, (2, 0));
+
+    img.put_pixel(0, 0, Rgb([0x40, 0x40, 0x40]));
+    assert!(matches!(
+        GBAImage::convert(&img, &opts),
+        Err(Error::UnknownColor(_))
+    ));
+
+    let strict = ConvertOptions {
+        palette: Some(palette),
+        ..Default::default()
+    };
+    img.put_pixel(0, 0, Rgb([0, 0, 0]));
+    assert!(matches!(
+        GBAImage::convert(&img, &strict),
+        Err(Error::UnknownColor(_))
+    ));
+}
+
+#[test]
+fn color_errors_point_at_offending_pixels_and_tiles() {
+    // 17 colors, with the 17th only in the bottom tile.
+    let img = RgbImage::from_fn(8, 16, |x, y| {
+        let shade = if y < 8 { (y * 8 + x) % 16 } else { 16 };
+        Rgb([(shade * 8) as u8, 0, 0])
+    });
+    let image = GBAImage::with_inferred_palette(&img).unwrap();
+    let Err(Error::TooManyColors(diagnosis)) = image.validate() else {
+        panic!("expected too many colors");
+    };
+    assert_eq!(diagnosis.colors, [(Color::rgb(0x80, 0, 0), (0, 8))]);
+    assert_eq!(diagnosis.pixels.len(), 64);
+    // The 17th color only overflows because of the top tile's 16, so every
+    // tile is counted, most colorful first.
+    assert_eq!(
+        diagnosis.tiles,
+        [
+            diagnose::TileColors {
+                x: 0,
+                y: 0,
+                colors: 16
+            },
+            diagnose::TileColors {
+                x: 0,
+                y: 1,
+                colors: 1
+            }
+        ]
+    );
+    assert!(diagnosis
+        .to_string()
+        .ends_with("tile (0, 0) uses 16 colors, tile (0, 1) uses 1 color"));
+
+    let rendered = diagnosis.render(&img, Preview::Raw);
+    assert_eq!(rendered.get_pixel(1, 9), &Rgb([0xFF, 0, 0xFF]));
+    assert_eq!(rendered.get_pixel(1, 0), &Rgb([0xFF, 0, 0]));
+    assert_eq!(rendered.get_pixel(1, 1), &Rgb([0x48 / 3, 0, 0]));
+    let expanded = diagnosis.render(&img, Preview::Expanded);
+    assert_eq!(expanded.get_pixel(1, 1), &Rgb([0x4A / 3, 0, 0]));
+
+    let palette = Palette::from(vec![Color::rgb(0, 0, 0)]);
+    let err = GBAImage::with_known_palette(&img, palette).err().unwrap();
+    let diagnosis = err.diagnosis().unwrap();
+    assert_eq!(diagnosis.colors.len(), 16);
+    assert_eq!(diagnosis.colors[0], (Color::rgb(8, 0, 0), (1, 0)));
+    assert_eq!(diagnosis.tiles.len(), 2);
+    assert!(err.to_string().contains("#080000 at (1, 0)"));
+
+    // One bad axis is enough.
+    for (width, height) in [(10, 8), (8, 12)] {
+        let img = RgbImage::new(width, height);
+        let image = GBAImage::with_inferred_palette(&img).unwrap();
+        let err = image.validate().err().unwrap();
+        assert!(matches!(err, Error::BadDimensions(w, h)
+            if (w, h) == (width as usize, height as usize)));
+    }
+    let odd = Error::BadDimensions(10, 8).diagnosis().unwrap();
+    assert_eq!(odd.pixels.len(), 16);
+}
+
+#[test]
+fn animation_frames_share_a_palette() {
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    // Each frame adds a color the others don't have.
+    let colors = [[0xF8, 0, 0, 0xFF], [0, 0xF8, 0, 0xFF], [0, 0, 0xF8, 0xFF]];
+    let mut gif = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut gif);
+        for (i, color) in colors.iter().enumerate() {
+            let image = RgbaImage::from_fn(8, 8, |x, _y| {
+                if x == 0 {
+                    Rgba([0, 0, 0, 0])
+                } else {
+                    Rgba(*color)
+                }
+            });
+            let delay = Delay::from_numer_denom_ms(100 * (i as u32 + 1), 1);
+            encoder
+                .encode_frame(Frame::from_parts(image, 0, 0, delay))
+                .unwrap();
+        }
+    }
+
+    let decoded = frames::decode_frames(&gif, Some(ImageFormat::Gif)).unwrap();
+    assert_eq!(decoded.len(), 3);
+    assert_eq!(decoded[1].delay_ms, 200);
+    assert_eq!(decoded[1].delay_frames(), 12);
+
+    let opts = ConvertOptions {
+        transparency: Some(Default::default()),
+        ..Default::default()
+    };
+    let sheet = frames::convert_frames(&decoded, &opts).unwrap();
+    assert_eq!((sheet.width, sheet.height), (8, 24));
+    assert_eq!(sheet.palette.len(), 4);
+    assert_eq!(sheet.pixel_at(0, 8), Some(0));
+    assert_eq!(sheet.color_at(1, 16), Some(Color::rgb(0, 0, 0xF8)));
+    assert_eq!(encode_tiles(sheet.tiles()).len(), 3 * 32);
+}
+
+#[test]
+fn images_can_be_edited_by_index() {
+    let palette = Palette::from(vec![
+        Color::rgb(0, 0, 0),
+        Color::rgb(0xF8, 0, 0),
+        Color::rgb(0, 0xF8, 0),
+    ]);
+
+    assert!(matches!(
+        GBAImage::from_indices(2, 2, vec![0; 3], palette.clone()),
+        Err(Error::IndexCount(3, 2, 2))
+    ));
+    assert!(matches!(
+        GBAImage::from_indices(2, 1, vec![0, 3], palette.clone()),
+        Err(Error::IndexOutOfRange(3, 3))
+    ));
+
+    let mut image =
+        GBAImage::from_indices(2, 2, vec![0, 1, 2, 1], palette.clone())
+            .unwrap();
+    image.set_pixel(0, 1, 2).unwrap();
+    assert!(image.set_pixel(2, 0, 1).is_err());
+    assert!(image.set_pixel(0, 0, 3).is_err());
+    assert_eq!(image.data, [0, 1, 2, 1]);
+
+    image.swap_palette_entries(1, 2).unwrap();
+    assert_eq!(image.data, [0, 2, 1, 2]);
+    assert_eq!(image.color_at(1, 0), Some(Color::rgb(0xF8, 0, 0)));
+
+    image.pad(1, 0, 0, 1).unwrap();
+    assert_eq!((image.width, image.height), (3, 3));
+    assert_eq!(image.data, [0, 0, 2, 0, 1, 2, 0, 0, 0]);
+
+    let mut canvas = GBAImage::blank(4, 4, palette).unwrap();
+    canvas.blit(&image.view(1, 0, 2, 2), 2, 2).unwrap();
+    assert_eq!(canvas.pixel_at(3, 2), Some(2));
+    assert_eq!(canvas.pixel_at(2, 3), Some(1));
+    assert!(canvas.blit(&image.view(0, 0, 3, 3), 2, 2).is_err());
+
+    canvas.crop(2, 2, 2, 2).unwrap();
+    assert_eq!(canvas.data, [0, 2, 1, 2]);
+    assert!(canvas.crop(1, 1, 2, 2).is_err());
+}
+
+#[test]
+fn font_glyphs_are_measured_and_packed() {
+    // Two 16x16 cells: a 5-pixel-wide bar in colors 1-3, then a blank cell.
+    let mut sheet = GBAImage::blank(
+        32,
+        16,
+        (0..4).map(|i| Color::rgb(i * 64, 0, 0)).collect(),
+    )
+    .unwrap();
+    for y in 2..12 {
+        for x in 1..6 {
+            sheet.set_pixel(x, y, x % 3 + 1).unwrap();
+        }
+    }
+
+    let opts = font::FontOptions::default();
+    let glyphs = font::glyphs(&sheet, &opts).unwrap();
+    assert_eq!(glyphs.len(), 2);
+    assert_eq!((glyphs[0].code, glyphs[1].code), (0x20, 0x21));
+    assert_eq!((glyphs[0].width, glyphs[1].width), (7, opts.blank_width));
+
+    // Each row is one little-endian word, leftmost pixel in the low bits.
+    let bytes = glyphs[0].encode(BitDepth::Two, font::GlyphLayout::Rows);
+    assert_eq!(bytes.len(), 16 * 4);
+    let row = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    assert_eq!(row, 0b11_10_01_11_10_00);
+
+    // 1bpp and 2bpp tiles survive a round trip.
+    for depth in [BitDepth::One, BitDepth::Two] {
+        let mut image = sheet.view(0, 0, 32, 16).to_image();
+        if depth == BitDepth::One {
+            image
+                .data
+                .iter_mut()
+                .for_each(|idx| *idx = (*idx > 0) as u8);
+        }
+        let encoded = encode_tiles_with_depth(image.tiles(), depth);
+        assert_eq!(encoded.len(), 8 * depth.tile_size());
+        let decoded = GBAImage::from_tiles_with_depth(
+            &encoded,
+            image.palette.clone(),
+            4,
+            depth,
+        )
+        .unwrap();
+        assert_eq!(decoded.data, image.data);
+    }
+
+    assert!(matches!(
+        font::glyphs(
+            &sheet,
+            &font::FontOptions {
+                cell_width: 12,
+                ..opts
+            }
+        ),
+        Err(Error::BadGlyphCell(12, 16))
+    ));
+}
+
+#[test]
+fn palettes_fade_and_cycle() {
+    let palette =
+        Palette::from(vec![Color::rgb(0xF8, 0x80, 0), Color::rgb(0, 0, 0)]);
+    let white = Color::rgb(0xFF, 0xFF, 0xFF);
+
+    // Steps are taken in 5-bit space and rounded to the nearest.
+    let fade = palette.fade(white, 3, 100);
+    assert_eq!(fade.len(), 3);
+    assert_eq!(fade[0].0, palette.0);
+    assert_eq!(
+        fade[1].0,
+        [Color::rgb(0xF8, 0xC0, 0x80), Color::rgb(0x80, 0x80, 0x80)]
+    );
+    assert_eq!(fade[2].0, [Color::rgb(0xF8, 0xF8, 0xF8); 2]);
+
+    // A tint stops partway.
+    let tint = palette.fade(white, 2, 50);
+    assert_eq!(tint[1].0, fade[1].0);
+
+    let ramp = (0..4).map(|i| Color::rgb(i * 8, 0, 0)).collect::<Palette>();
+    let c = |i: usize| ramp.0[i];
+    let cycle = ramp.cycle(1..4, 3, false).unwrap();
+    assert_eq!(cycle[0].0, ramp.0);
+    assert_eq!(cycle[1].0, [c(0), c(3), c(1), c(2)]);
+    assert_eq!(cycle[2].0, [c(0), c(2), c(3), c(1)]);
+    let reversed = ramp.cycle(1..4, 2, true).unwrap();
+    assert_eq!(reversed[1].0, [c(0), c(2), c(3), c(1)]);
+
+    assert!(matches!(
+        ramp.cycle(2..5, 2, false),
+        Err(Error::BadPaletteRange(2, 4, 4))
+    ));
+}
+
+#[test]
+fn previews_expand_and_correct_gba_colors() {
+    let white = Color::from_16bit(0x7FFF);
+    let black = Color::from_16bit(0);
+    assert_eq!(white, Color::rgb(0xF8, 0xF8, 0xF8));
+
+    // Bit replication fills the whole range.
+    assert_eq!(
+        white.preview(Preview::Expanded),
+        Color::rgb(0xFF, 0xFF, 0xFF)
+    );
+    assert_eq!(
+        Color::from_16bit(0x0201).preview(Preview::Expanded),
+        Color::rgb(0x08, 0x84, 0)
+    );
+
+    // The LCD's white is slightly off, and pure red bleeds into the others.
+    assert_eq!(black.preview(Preview::Lcd), black);
+    let lcd_white = white.preview(Preview::Lcd);
+    assert!(lcd_white.r > 0xE0 && lcd_white.g > 0xE0 && lcd_white.b > 0xE0);
+    let lcd_red = Color::from_16bit(0x1F).preview(Preview::Lcd);
+    assert!(lcd_red.r > 0xE0 && lcd_red.g > 0 && lcd_red.b > 0);
+
+    // Only the PNG's palette changes.
+    let image = GBAImage::from_indices(
+        2,
+        1,
+        vec![1, 0],
+        Palette::from(vec![black, white]),
+    )
+    .unwrap();
+    let png = image.encode_png_with_preview(Preview::Expanded).unwrap();
+    let decoded = GBAImage::from_indexed_png(&png, 16, None).unwrap().unwrap();
+    assert_eq!(decoded.data, image.data);
+    assert_eq!(decoded.palette.0, [black, Color::rgb(0xFF, 0xFF, 0xFF)]);
+}